@@ -9,7 +9,6 @@ use std::collections::HashMap;
 use yew_hooks::UseClipboardHandle;
 
 use gloo_timers::callback::Timeout;
-use hemoglobin::cards::Card;
 use serde::Deserialize;
 use yew::prelude::*;
 use yew_router::history::AnyHistory;
@@ -20,7 +19,7 @@ use yew_router::Router;
 
 // static QUERY: Mutex<String> = Mutex::new(String::new());
 #[cfg(not(debug_assertions))]
-static HOST: &'static str = "https://hemolymph.net";
+pub static HOST: &str = "https://hemolymph.net";
 
 #[cfg(debug_assertions)]
 pub static HOST: &str = "http://127.0.0.1:8080";
@@ -37,12 +36,48 @@ enum Route {
     Instructions,
 }
 
+/// The fields of a card the search grid asks for.
+const CARD_PREVIEW_FIELDS: &str = "id,image";
+
+/// A search result, with only the fields in `CARD_PREVIEW_FIELDS`.
+#[derive(Deserialize, PartialEq)]
+struct CardPreview {
+    id: String,
+    image: String,
+}
+
+/// A query the server suggests when a search finds nothing.
+#[derive(Deserialize, PartialEq)]
+struct Alternative {
+    query: String,
+    results: usize,
+}
+
+/// Where the words of a search matched in one of a result's fields, as byte offsets into `text`.
+#[derive(Deserialize, PartialEq)]
+struct FieldMatch {
+    field: String,
+    text: String,
+    spans: Vec<[usize; 2]>,
+}
+
+#[derive(Deserialize, PartialEq)]
+struct Relevance {
+    score: f64,
+    matches: Vec<FieldMatch>,
+}
+
 #[derive(Deserialize, PartialEq)]
 #[serde(tag = "type")]
 enum QueryResult {
     CardList {
         query_text: String,
-        content: Vec<Card>,
+        content: Vec<CardPreview>,
+        /// In the same order as `content`.
+        #[serde(default)]
+        relevance: Vec<Relevance>,
+        #[serde(default)]
+        alternatives: Vec<Alternative>,
     },
     Error {
         message: String,
@@ -244,11 +279,21 @@ fn instructions() -> Html {
 
 fn get_filegarden_link(name: &str) -> String {
     format!(
-        "https://file.garden/ZJSEzoaUL3bz8vYK/bloodlesscards/{}.png",
+        "{HOST}/api/image/{}.png",
         name.replace(' ', "").replace('ä', "a")
     )
 }
 
+/// Resized versions of a card's image, for grids that show cards at `.card-result`'s 200px width.
+fn get_filegarden_srcset(name: &str) -> String {
+    let link = get_filegarden_link(name);
+    [200, 400]
+        .iter()
+        .map(|width| format!("{link}?size={width}&format=webp {width}w"))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
 #[hook]
 fn use_clipboard() -> Option<UseClipboardHandle> {
     let clipboard;
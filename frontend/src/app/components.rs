@@ -1,3 +1,5 @@
 pub mod card_details;
 pub mod card_grid;
+pub mod mentioned_by;
 pub mod search_results;
+pub mod similar_cards;
@@ -1,9 +1,11 @@
 use crate::app::components::card_grid::CardThumbnail;
+use crate::app::components::mentioned_by::MentionedBy;
+use crate::app::components::similar_cards::SimilarCards;
 use hemoglobin::cards::rich_text::RichElement;
 use hemoglobin::cards::{rich_text::RichString, Card};
 use reqwest::Client;
 use yew::suspense::use_future_with;
-use yew::{function_component, html, Callback, Html, HtmlResult, MouseEvent, Properties};
+use yew::{function_component, html, Callback, Html, HtmlResult, MouseEvent, Properties, Suspense};
 use yew_router::components::Link;
 
 use crate::app::{get_ascii_titlecase, get_filegarden_link, modify_title, Route};
@@ -103,6 +105,12 @@ pub fn card_details(CardDetailsProps { card_id, img_index }: &CardDetailsProps)
                             {for alts}
                         </div>
                     }
+                    <Suspense>
+                        <MentionedBy card_id={card.id.clone()} />
+                    </Suspense>
+                    <Suspense>
+                        <SimilarCards card_id={card.id.clone()} />
+                    </Suspense>
                 </div>
             })
         }
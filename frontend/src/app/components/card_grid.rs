@@ -1,7 +1,7 @@
 use yew::{function_component, html, Callback, Html, MouseEvent, Properties};
 use yew_router::components::Link;
 
-use crate::app::{get_filegarden_link, use_clipboard, Route};
+use crate::app::{get_filegarden_link, get_filegarden_srcset, use_clipboard, Route};
 
 #[derive(Properties, PartialEq, Eq)]
 pub struct CardThumbnailProps {
@@ -25,7 +25,7 @@ pub fn card_thumbnail(
         1 => authors
             .first()
             .expect("Authors field was empty even though it verifiably was full")
-            .to_string(),
+            .clone(),
         _ => format!(
             "{} et al",
             authors
@@ -46,7 +46,7 @@ pub fn card_thumbnail(
         html! {
             <div class="card-alt-view">
                 <span class="art-author">{authors}</span>
-                <Link<Route> to={Route::Card{id: id.clone()}}><img class="card-result" src={get_filegarden_link(image)} /></Link<Route>>
+                <Link<Route> to={Route::Card{id: id.clone()}}><img class="card-result" src={get_filegarden_link(image)} srcset={get_filegarden_srcset(image)} sizes="200px" /></Link<Route>>
                 <button onclick={copy_id}>{"Copy Marrow ID"}</button>
             </div>
         }
@@ -54,7 +54,7 @@ pub fn card_thumbnail(
         html! {
             <div class="card-alt-view">
                 <span class="art-author">{authors}</span>
-                <Link<Route> to={Route::CardArt{ id: id.clone(), index: *art }}><img class="card-result" src={get_filegarden_link(image)} /></Link<Route>>
+                <Link<Route> to={Route::CardArt{ id: id.clone(), index: *art }}><img class="card-result" src={get_filegarden_link(image)} srcset={get_filegarden_srcset(image)} sizes="200px" /></Link<Route>>
                 <button onclick={copy_id}>{"Copy Marrow ID"}</button>
             </div>
         }
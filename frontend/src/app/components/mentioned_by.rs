@@ -0,0 +1,49 @@
+use hemoglobin::cards::Card;
+use reqwest::Client;
+use yew::suspense::use_future_with;
+use yew::{function_component, html, HtmlResult, Properties};
+
+use crate::app::components::card_grid::CardThumbnail;
+use crate::app::HOST;
+
+#[derive(Properties, Eq, PartialEq)]
+pub struct MentionedByProps {
+    pub card_id: String,
+}
+
+/// Cards whose descriptions link to this card.
+#[function_component(MentionedBy)]
+pub fn mentioned_by(MentionedByProps { card_id }: &MentionedByProps) -> HtmlResult {
+    let sources = use_future_with(card_id.to_owned(), |card_id| async move {
+        let client = Client::new();
+        let url = format!("{HOST}/api/card/{card_id}/backlinks");
+        match client.get(&url).send().await {
+            Ok(response) => response.json::<Vec<Card>>().await.unwrap_or_default(),
+            Err(_) => vec![],
+        }
+    })?;
+
+    if sources.is_empty() {
+        return Ok(html! {});
+    }
+
+    let cards = sources.iter().map(|card| {
+        let authors = card
+            .images
+            .first()
+            .map(|x| x.authors.clone())
+            .unwrap_or_default();
+        html! {
+            <CardThumbnail id={card.id.clone()} image={card.get_image_path(0)} art=0 {authors}/>
+        }
+    });
+
+    Ok(html! {
+        <>
+            <h2 class="center-text">{"Mentioned By"}</h2>
+            <div id="mentioned-by" class="card-grid">
+                {for cards}
+            </div>
+        </>
+    })
+}
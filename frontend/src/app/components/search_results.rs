@@ -1,15 +1,23 @@
 use crate::app::use_clipboard;
 use crate::app::Route;
 use crate::app::HOST;
-use crate::app::{get_filegarden_link, modify_title, QueryResult};
+use crate::app::{
+    get_filegarden_link, get_filegarden_srcset, modify_title, QueryResult, Relevance,
+    CARD_PREVIEW_FIELDS,
+};
 use reqwest::Client;
+use serde_json::json;
 use yew::html;
 use yew::AttrValue;
 use yew::Callback;
+use yew::Html;
 use yew::MouseEvent;
 use yew::{function_component, suspense::use_future_with, HtmlResult, Properties};
 use yew_router::components::Link;
 
+/// The most cards the server puts in a single proxy sheet.
+const MAX_PROXIES: usize = 360;
+
 #[derive(Properties, PartialEq)]
 pub struct CardListProps {
     pub search: AttrValue,
@@ -31,8 +39,14 @@ pub fn search_results(
     force_text_fn.emit(search.clone());
     let result = use_future_with(search.clone(), |search| async move {
         let client = Client::new();
-        let url = format!("{HOST}/api/search?query={}", search.clone());
-        match client.get(&url).send().await {
+        // Passed as query parameters so that characters like `&` and `#` in the search are encoded.
+        let params = [("query", search.as_str()), ("fields", CARD_PREVIEW_FIELDS)];
+        match client
+            .get(format!("{HOST}/api/search"))
+            .query(&params)
+            .send()
+            .await
+        {
             Ok(response) => match response.json::<QueryResult>().await {
                 Ok(queryres) => queryres,
                 Err(err) => QueryResult::Error {
@@ -49,12 +63,15 @@ pub fn search_results(
         QueryResult::CardList {
             ref query_text,
             ref content,
+            ref relevance,
+            ref alternatives,
         } => {
             let cards = content
                 .iter()
-                .map(|card| {
+                .enumerate()
+                .map(|(idx, card)| {
                     let clipboard = clipboard.clone();
-                    let image_id = card.get_image_path(0);
+                    let image_id = card.image.clone();
                     let image_id_clone = image_id.clone();
                     let copy_id = Callback::from(move |_: MouseEvent| {
                         let clipboard = clipboard.clone();
@@ -62,15 +79,39 @@ pub fn search_results(
                     });
                     html! {
                         <div class="card_result">
-                            <Link<Route> to={Route::Card{id: card.id.clone()}}><img class="card-result" src={get_filegarden_link(&image_id)} /></Link<Route>>
+                            <Link<Route> to={Route::Card{id: card.id.clone()}}><img class="card-result" src={get_filegarden_link(&image_id)} srcset={get_filegarden_srcset(&image_id)} sizes="200px" /></Link<Route>>
                             <button onclick={copy_id}>{"Copy Marrow ID"}</button>
+                            {relevance.get(idx).map(match_info)}
                         </div>
                     }
                 });
+            // One copy of every result. The form opens the sheet in a new tab, since it's a PDF.
+            let proxies = json!(content
+                .iter()
+                .map(|card| json!({"id": card.id}))
+                .collect::<Vec<_>>())
+            .to_string();
+            let alternative_links = alternatives.iter().map(|alt| {
+                html! {
+                    <li><Link<Route> to={Route::Search{query: alt.query.clone()}}>{&alt.query}</Link<Route>>{format!(" ({} results)", alt.results)}</li>
+                }
+            });
 
             Ok(html! {
                 <>
                     <p id="query_readable">{"Showing "}{cards.len()}{" "}{query_text}</p>
+                    if (1..=MAX_PROXIES).contains(&content.len()) {
+                        <form id="print-proxies" method="post" action={format!("{HOST}/api/deck/proxies?cut_marks=true")} target="_blank">
+                            <input type="hidden" name="deck" value={proxies} />
+                            <button type="submit">{"Print proxies"}</button>
+                        </form>
+                    }
+                    if !alternatives.is_empty() {
+                        <div id="alternatives">
+                            <p>{"Did you mean:"}</p>
+                            <ul>{for alternative_links}</ul>
+                        </div>
+                    }
                     <div id="results" class="card-grid">
                         {for cards}
                     </div>
@@ -84,3 +125,37 @@ pub fn search_results(
         }),
     }
 }
+
+/// Marks the parts of `text` that a search matched.
+fn highlight(text: &str, spans: &[[usize; 2]]) -> Html {
+    let mut parts = vec![];
+    let mut last = 0;
+    for &[start, end] in spans {
+        let (Some(before), Some(matched)) = (text.get(last..start), text.get(start..end)) else {
+            continue;
+        };
+        parts.push(html! {{before}});
+        parts.push(html! {<mark>{matched}</mark>});
+        last = end;
+    }
+    parts.push(html! {{text.get(last..).unwrap_or_default()}});
+    parts.into_iter().collect()
+}
+
+/// Shows why a card matched a search when hovering over it.
+fn match_info(relevance: &Relevance) -> Html {
+    if relevance.matches.is_empty() {
+        return html! {};
+    }
+    let matches = relevance.matches.iter().map(|x| {
+        html! {
+            <p><span class="match-field">{&x.field}{": "}</span>{highlight(&x.text, &x.spans)}</p>
+        }
+    });
+    html! {
+        <div class="match-info">
+            {for matches}
+            <p>{format!("Score: {:.2}", relevance.score)}</p>
+        </div>
+    }
+}
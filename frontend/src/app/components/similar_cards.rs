@@ -0,0 +1,58 @@
+use hemoglobin::cards::Card;
+use reqwest::Client;
+use serde::Deserialize;
+use yew::suspense::use_future_with;
+use yew::{function_component, html, HtmlResult, Properties};
+
+use crate::app::components::card_grid::CardThumbnail;
+use crate::app::HOST;
+
+#[derive(Properties, Eq, PartialEq)]
+pub struct SimilarCardsProps {
+    pub card_id: String,
+}
+
+#[derive(Deserialize, PartialEq)]
+struct Similar {
+    card: Card,
+    reason: String,
+}
+
+#[function_component(SimilarCards)]
+pub fn similar_cards(SimilarCardsProps { card_id }: &SimilarCardsProps) -> HtmlResult {
+    let similar = use_future_with(card_id.to_owned(), |card_id| async move {
+        let client = Client::new();
+        let url = format!("{HOST}/api/card/{card_id}/similar");
+        match client.get(&url).send().await {
+            Ok(response) => response.json::<Vec<Similar>>().await.unwrap_or_default(),
+            Err(_) => vec![],
+        }
+    })?;
+
+    if similar.is_empty() {
+        return Ok(html! {});
+    }
+
+    let cards = similar.iter().map(|Similar { card, reason }| {
+        let authors = card
+            .images
+            .first()
+            .map(|x| x.authors.clone())
+            .unwrap_or_default();
+        html! {
+            <div class="similar-card">
+                <CardThumbnail id={card.id.clone()} image={card.get_image_path(0)} art=0 {authors}/>
+                <p class="similar-reason">{reason}</p>
+            </div>
+        }
+    });
+
+    Ok(html! {
+        <>
+            <h2 class="center-text">{"Similar Cards"}</h2>
+            <div id="similar-cards" class="card-grid">
+                {for cards}
+            </div>
+        </>
+    })
+}
@@ -1,4 +1,5 @@
 mod app;
 
+pub use app::App;
 pub use app::ServerApp;
 pub use app::ServerAppProps;
@@ -1,5 +1,4 @@
-mod app;
-use app::App;
+use hemolymph_frontend::App;
 
 pub fn main() {
     let x = yew::Renderer::<App>::new();
@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use hemoglobin::search::query_parser::query_parser;
+use serde::Deserialize;
+
+use crate::{images, AppState};
+
+pub const ALIASES_PATH: &str = "./static/aliases.json";
+/// Aliases can expand to other aliases many times over, so expansions are cut off at this length.
+const MAX_EXPANSION_LENGTH: usize = 10_000;
+
+const fn is_alias_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_' || ch == '-'
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    if !name.is_empty() && name.chars().all(is_alias_char) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Not a valid alias name: {name}. Names can only have letters, numbers, - and _"
+        ))
+    }
+}
+
+fn check_length(expanded: &str) -> Result<(), String> {
+    if expanded.len() > MAX_EXPANSION_LENGTH {
+        return Err(format!(
+            "Aliases expand to a query longer than {MAX_EXPANSION_LENGTH} characters"
+        ));
+    }
+    Ok(())
+}
+
+/// Replaces every `@alias` in a query with its query, in parentheses so it stays one term. Aliases inside quoted values and regexes are left alone.
+pub fn expand(query: &str, aliases: &HashMap<String, String>) -> Result<String, String> {
+    expand_inner(query, aliases, &mut vec![])
+}
+
+fn expand_inner<'a>(
+    query: &str,
+    aliases: &'a HashMap<String, String>,
+    expanding: &mut Vec<&'a str>,
+) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut delimiter = None;
+    let mut previous = ' ';
+    let mut chars = query.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match (delimiter, ch) {
+            (Some(end), ch) if ch == end => delimiter = None,
+            (None, '"') => delimiter = Some('"'),
+            (None, '/') if previous == ':' => delimiter = Some('/'),
+            (None, '@') if matches!(previous, ' ' | '(' | '-') => {
+                let mut name = String::new();
+                while let Some(ch) = chars.next_if(|x| is_alias_char(*x)) {
+                    name.push(ch);
+                }
+                let name = name.to_lowercase();
+                let (name, alias) = aliases
+                    .get_key_value(&name)
+                    .ok_or_else(|| format!("Unknown alias: @{name}"))?;
+                if expanding.contains(&name.as_str()) {
+                    expanding.push(name);
+                    return Err(format!(
+                        "Aliases refer to each other in a cycle: @{}",
+                        expanding.join(" -> @")
+                    ));
+                }
+                expanding.push(name);
+                let alias = expand_inner(alias, aliases, expanding)?;
+                expanding.pop();
+                expanded.push('(');
+                expanded.push_str(&alias);
+                expanded.push(')');
+                // Checked as the expansion grows, so aliases that double in size at every level stop before they use up memory.
+                check_length(&expanded)?;
+                previous = ')';
+                continue;
+            }
+            _ => (),
+        }
+        expanded.push(ch);
+        check_length(&expanded)?;
+        previous = ch;
+    }
+    Ok(expanded)
+}
+
+/// Checks that every alias has a valid name and expands to a query that can be parsed.
+fn validate(aliases: &HashMap<String, String>) -> Result<(), String> {
+    for (name, query) in aliases {
+        validate_name(name)?;
+        let expanded = expand(query, aliases).map_err(|x| format!("@{name}: {x}"))?;
+        query_parser(&expanded).map_err(|x| format!("@{name} couldn't be parsed: {x:#?}"))?;
+    }
+    Ok(())
+}
+
+/// Reads aliases from a JSON object of names and queries. Aliases that form a cycle make the whole file invalid.
+pub fn load_aliases(path: impl AsRef<Path>) -> Result<HashMap<String, String>, String> {
+    let data = fs::read_to_string(path).map_err(|x| x.to_string())?;
+    let aliases: HashMap<String, String> =
+        serde_json::from_str(&data).map_err(|x| x.to_string())?;
+    let aliases = aliases
+        .into_iter()
+        .map(|(name, query)| (name.to_lowercase(), query))
+        .collect();
+    validate(&aliases)?;
+    Ok(aliases)
+}
+
+/// Writes aliases sorted by name. The file is replaced all at once, so it's never left half written if the server stops midway.
+fn save_aliases(path: &Path, aliases: &HashMap<String, String>) -> Result<(), String> {
+    let sorted: BTreeMap<&String, &String> = aliases.iter().collect();
+    let data = serde_json::to_string_pretty(&sorted).map_err(|x| x.to_string())?;
+    images::write_atomically(path, data.as_bytes()).map_err(|x| x.to_string())
+}
+
+/// Compares every byte, so how long a comparison takes doesn't say how much of the token was right.
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Changing aliases needs the `ADMIN_TOKEN` environment variable to be set, and requests to send it as a bearer token. Returns the response to refuse the request with, if it's not allowed.
+fn refusal(req: &HttpRequest) -> Option<HttpResponse> {
+    let Ok(token) = env::var("ADMIN_TOKEN") else {
+        return Some(HttpResponse::Forbidden().body("Aliases can't be changed on this server"));
+    };
+    let sent = req
+        .headers()
+        .get("Authorization")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+    match sent {
+        Some(sent) if !token.is_empty() && tokens_match(sent, &token) => None,
+        _ => Some(HttpResponse::Unauthorized().body("Not a valid admin token")),
+    }
+}
+
+pub async fn list_aliases(data: web::Data<AppState>) -> impl Responder {
+    let aliases = data.aliases.read().await;
+    let sorted: BTreeMap<&String, &String> = aliases.iter().collect();
+    HttpResponse::Ok().json(sorted)
+}
+
+#[derive(Deserialize)]
+pub struct AliasBody {
+    query: String,
+}
+
+pub async fn set_alias(
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+    body: web::Json<AliasBody>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = refusal(&req) {
+        return response;
+    }
+    let name = name.to_lowercase();
+    let mut aliases = data.aliases.write().await;
+    let mut changed = aliases.clone();
+    changed.insert(name, body.query.clone());
+    if let Err(message) = validate(&changed) {
+        return HttpResponse::BadRequest().body(message);
+    }
+    if let Err(message) = save_aliases(Path::new(ALIASES_PATH), &changed) {
+        return HttpResponse::InternalServerError().body(message);
+    }
+    *aliases = changed;
+    HttpResponse::NoContent().finish()
+}
+
+pub async fn delete_alias(
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = refusal(&req) {
+        return response;
+    }
+    let name = name.to_lowercase();
+    let mut aliases = data.aliases.write().await;
+    let mut changed = aliases.clone();
+    if changed.remove(&name).is_none() {
+        return HttpResponse::NotFound().body(format!("Unknown alias: @{name}"));
+    }
+    // Other aliases might have used this one.
+    if let Err(message) = validate(&changed) {
+        return HttpResponse::BadRequest().body(message);
+    }
+    if let Err(message) = save_aliases(Path::new(ALIASES_PATH), &changed) {
+        return HttpResponse::InternalServerError().body(message);
+    }
+    *aliases = changed;
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use super::{expand, load_aliases, save_aliases, validate, MAX_EXPANSION_LENGTH};
+
+    fn aliases(aliases: &[(&str, &str)]) -> HashMap<String, String> {
+        aliases
+            .iter()
+            .map(|(name, query)| ((*name).to_string(), (*query).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn expansion() {
+        let aliases = aliases(&[("bug", "k:insect"), ("cheap_bug", "@bug c<2")]);
+        assert_eq!(expand("@bug", &aliases).unwrap(), "(k:insect)");
+        assert_eq!(
+            expand("-@Cheap_Bug OR (@bug)", &aliases).unwrap(),
+            "-((k:insect) c<2) OR ((k:insect))"
+        );
+        // Only whole terms are aliases, and quoted values and regexes are left alone.
+        assert_eq!(
+            expand(r#"me@bug n:"@bug" desc:/@bug/ @bug"#, &aliases).unwrap(),
+            r#"me@bug n:"@bug" desc:/@bug/ (k:insect)"#
+        );
+        assert_eq!(
+            expand("@moth", &aliases),
+            Err("Unknown alias: @moth".to_string())
+        );
+    }
+
+    #[test]
+    fn cycles() {
+        let aliases = aliases(&[("a", "@b"), ("b", "n:x @c"), ("c", "@a"), ("d", "@d")]);
+        assert_eq!(
+            expand("@a", &aliases),
+            Err("Aliases refer to each other in a cycle: @a -> @b -> @c -> @a".to_string())
+        );
+        assert_eq!(
+            expand("@d", &aliases),
+            Err("Aliases refer to each other in a cycle: @d -> @d".to_string())
+        );
+        assert!(validate(&aliases).is_err());
+        // The same alias can be used twice as long as it doesn't contain itself.
+        let aliases = self::aliases(&[("a", "@b @b"), ("b", "x")]);
+        assert_eq!(expand("@a", &aliases).unwrap(), "((x) (x))");
+        assert!(validate(&aliases).is_ok());
+    }
+
+    #[test]
+    fn length_limit() {
+        // Every level doubles the length of the expansion, so the last one could never be written out in full.
+        let mut levels = vec![("a0".to_string(), "x".repeat(10))];
+        for level in 1..64 {
+            levels.push((format!("a{level}"), format!("@a{0} @a{0}", level - 1)));
+        }
+        let aliases: HashMap<String, String> = levels.into_iter().collect();
+        assert!(expand("@a8", &aliases).is_ok());
+        assert_eq!(
+            expand("@a63", &aliases),
+            Err(format!(
+                "Aliases expand to a query longer than {MAX_EXPANSION_LENGTH} characters"
+            ))
+        );
+    }
+
+    #[test]
+    fn saving() {
+        let dir = std::env::temp_dir().join(format!("hemolymph-aliases-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("aliases.json");
+        fs::write(&path, "{\"old\": \"x\"}").unwrap();
+
+        let saved = aliases(&[("moth", "n:moth"), ("bug", "k:insect")]);
+        save_aliases(&path, &saved).unwrap();
+        assert_eq!(load_aliases(&path), Ok(saved));
+        // The file is written next to the old one and moved over it, so no temporary file is left behind.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.find("\"bug\"") < text.find("\"moth\""));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_web::web;
+use hemoglobin::cards::Card;
+use hemoglobin::search::query_parser::query_parser;
+use serde::Serialize;
+
+use crate::suggest::{parameter_context, Context, Entry, Vocabulary};
+use crate::{aliases, matching, normalize, AppState};
+
+/// How similar a term has to be to a known value to be corrected to it.
+const CORRECTION_THRESHOLD: f64 = 0.7;
+const MAX_ALTERNATIVES: usize = 5;
+/// Every term is corrected and dropped in turn, so queries with more terms than this don't get alternatives.
+const MAX_TERMS: usize = 16;
+/// Longer terms aren't corrected, since comparing them to every known value is slow and they're unlikely to be a single misspelled word.
+const MAX_CORRECTED_LENGTH: usize = 64;
+/// How many close queries are searched with at most.
+const MAX_CANDIDATES: usize = 12;
+/// How long looking for alternatives can take. Whatever was found by then is returned.
+const TIME_LIMIT: Duration = Duration::from_secs(1);
+
+/// A query that's close to one that found nothing, but finds something.
+#[derive(Serialize)]
+pub struct Alternative {
+    query: String,
+    results: usize,
+}
+
+/// Splits a query into its top level terms, keeping quoted values, regexes and groups whole.
+fn split_terms(query: &str) -> Vec<&str> {
+    let mut terms = vec![];
+    let mut start = 0;
+    let mut depth = 0_usize;
+    let mut delimiter = None;
+    for (idx, ch) in query.char_indices() {
+        match (delimiter, ch) {
+            (Some(end), ch) if ch == end => delimiter = None,
+            (None, '"' | '/') => delimiter = Some(ch),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, ' ') if depth == 0 => {
+                if start < idx {
+                    terms.push(&query[start..idx]);
+                }
+                start = idx + 1;
+            }
+            _ => (),
+        }
+    }
+    if start < query.len() {
+        terms.push(&query[start..]);
+    }
+    terms
+}
+
+/// The known value closest to `value`, and what kind of value it is, if any is close enough.
+fn closest<'a>(
+    value: &str,
+    candidates: impl Iterator<Item = (&'a Entry, Context)>,
+) -> Option<(&'a str, Context)> {
+    let value = normalize::fold(value);
+    candidates
+        .map(|(candidate, context)| {
+            let similarity = strsim::normalized_damerau_levenshtein(&candidate.folded, &value);
+            (
+                candidate.value.as_str(),
+                context,
+                similarity,
+                candidate.folded == value,
+            )
+        })
+        // Values that only differ in case or accents already match, so they aren't corrections.
+        .filter(|(_, _, similarity, same)| *similarity >= CORRECTION_THRESHOLD && !same)
+        .max_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(candidate, context, _, _)| (candidate, context))
+}
+
+fn quote(value: &str) -> String {
+    if value.contains(' ') {
+        format!("\"{value}\"")
+    } else {
+        value.to_string()
+    }
+}
+
+/// A corrected version of a term, if it looks misspelled.
+fn correct(term: &str, vocabulary: &Vocabulary) -> Option<String> {
+    if term.len() > MAX_CORRECTED_LENGTH {
+        return None;
+    }
+    let value = term.trim_start_matches('-');
+    let negation = &term[..term.len() - value.len()];
+
+    if let Some((parameter, value)) = value.split_once(':') {
+        let context = parameter_context(parameter)?;
+        let (corrected, _) = closest(
+            value.trim_matches('"'),
+            vocabulary.values(context).iter().map(|x| (x, context)),
+        )?;
+        return Some(format!("{negation}{parameter}:{}", quote(corrected)));
+    }
+
+    if value.contains(['(', '"', '/', '<', '>', '=', '!', '@']) || value == "OR" || value == "XOR" {
+        return None;
+    }
+    // Bare words can be misspelled names, but also kins or keywords, which are searched for with their own parameters.
+    let candidates = vocabulary
+        .name_words()
+        .iter()
+        .map(|x| (x, Context::Name))
+        .chain(
+            vocabulary
+                .values(Context::Kin)
+                .iter()
+                .map(|x| (x, Context::Kin)),
+        )
+        .chain(
+            vocabulary
+                .values(Context::Keyword)
+                .iter()
+                .map(|x| (x, Context::Keyword)),
+        );
+    let (corrected, context) = closest(value, candidates)?;
+    Some(match context {
+        Context::Kin => format!("{negation}k:{}", quote(corrected)),
+        Context::Keyword => format!("{negation}kw:{}", quote(corrected)),
+        _ => format!("{negation}{corrected}"),
+    })
+}
+
+/// How many cards a query finds, or `None` if searching ran past `deadline`. Queries that can't be parsed find nothing.
+fn count_results(
+    query: &str,
+    cards: &HashMap<String, Card>,
+    search_cards: &HashMap<String, Card>,
+    aliases: &HashMap<String, String>,
+    deadline: Instant,
+) -> Option<usize> {
+    let Ok(query) = aliases::expand(query, aliases) else {
+        return Some(0);
+    };
+    query_parser(&query).map_or(Some(0), |query| {
+        matching::search_until(&query, &search_cards.values(), cards, deadline).map(|x| x.len())
+    })
+}
+
+/// Finds queries close to one that found nothing, without holding up the async workers, since each one is another search.
+pub async fn find(data: &web::Data<AppState>, query: String) -> Vec<Alternative> {
+    let cards = Arc::clone(&data.cards);
+    let search_cards = Arc::clone(&data.search_cards);
+    let aliases = Arc::clone(&data.aliases);
+    let vocabulary = Arc::clone(&data.vocabulary);
+    web::block(move || {
+        alternatives(
+            &query,
+            &cards.blocking_read(),
+            &search_cards.blocking_read(),
+            &aliases.blocking_read(),
+            &vocabulary.blocking_read(),
+            Instant::now() + TIME_LIMIT,
+        )
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// Finds queries close to one that found nothing: first by correcting misspelled terms, then by dropping terms one at a time. Stops at `deadline` with whatever it found by then.
+fn alternatives(
+    query: &str,
+    cards: &HashMap<String, Card>,
+    search_cards: &HashMap<String, Card>,
+    aliases: &HashMap<String, String>,
+    vocabulary: &Vocabulary,
+    deadline: Instant,
+) -> Vec<Alternative> {
+    let terms = split_terms(query);
+    if terms.len() > MAX_TERMS {
+        return vec![];
+    }
+
+    let corrections: Vec<Option<String>> =
+        terms.iter().map(|term| correct(term, vocabulary)).collect();
+    let with = |replace: &dyn Fn(usize) -> Option<String>| -> String {
+        terms
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, term)| replace(idx).or_else(|| Some((*term).to_string())))
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let mut candidates = vec![];
+    if corrections.iter().flatten().count() > 1 {
+        candidates.push(with(&|idx| corrections[idx].clone()));
+    }
+    for (idx, correction) in corrections.iter().enumerate() {
+        if correction.is_some() {
+            candidates.push(with(&|other| {
+                (other == idx).then(|| correction.clone()).flatten()
+            }));
+        }
+    }
+    if terms.len() > 1 {
+        for (idx, term) in terms.iter().enumerate() {
+            if *term != "OR" && *term != "XOR" {
+                candidates.push(with(&|other| (other == idx).then(String::new)));
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    candidates.retain(|x| x != query && seen.insert(x.clone()));
+    let mut alternatives: Vec<Alternative> = vec![];
+    for candidate in candidates.into_iter().take(MAX_CANDIDATES) {
+        if alternatives.len() >= MAX_ALTERNATIVES {
+            break;
+        }
+        let Some(results) = count_results(&candidate, cards, search_cards, aliases, deadline)
+        else {
+            break;
+        };
+        if results > 0 {
+            alternatives.push(Alternative {
+                query: candidate,
+                results,
+            });
+        }
+    }
+    alternatives
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use hemoglobin::cards::Card;
+    use serde_json::json;
+
+    use super::{alternatives, correct, split_terms};
+    use crate::normalize;
+    use crate::suggest::Vocabulary;
+
+    fn cards() -> HashMap<String, Card> {
+        [
+            ("mantis", "Vampire Mantis", "bug", 2),
+            ("moth", "Moth", "bug", 1),
+            ("mole", "Mole Rat", "mammal", 3),
+        ]
+        .into_iter()
+        .map(|(id, name, kin, cost)| {
+            let card = json!({
+                "id": id, "name": name, "description": "", "cost": cost, "health": 1,
+                "defense": 0, "power": 1, "type": "creature", "kins": [kin], "legality": {},
+            });
+            (
+                id.to_string(),
+                serde_json::from_str(&card.to_string()).unwrap(),
+            )
+        })
+        .collect()
+    }
+
+    #[test]
+    fn splitting_terms() {
+        assert_eq!(split_terms("  mantis  k:bug "), ["mantis", "k:bug"]);
+        // Quotes, regexes and groups stay whole, even with spaces inside them.
+        assert_eq!(
+            split_terms(r#"n:"vampire mantis" (k:bug OR c:1) n:/a b/ -moth"#),
+            [
+                r#"n:"vampire mantis""#,
+                "(k:bug OR c:1)",
+                "n:/a b/",
+                "-moth"
+            ]
+        );
+        assert_eq!(split_terms("(a (b c)) d"), ["(a (b c))", "d"]);
+        assert!(split_terms("").is_empty());
+    }
+
+    #[test]
+    fn corrections() {
+        let vocabulary = Vocabulary::new(&cards());
+        assert_eq!(correct("mnatis", &vocabulary), Some("mantis".to_string()));
+        assert_eq!(
+            correct("-k:mamal", &vocabulary),
+            Some("-k:mammal".to_string())
+        );
+        assert_eq!(
+            correct("n:\"vampire mantsi\"", &vocabulary),
+            Some("n:\"Vampire Mantis\"".to_string())
+        );
+        // Bare words can also be kins.
+        assert_eq!(correct("mammel", &vocabulary), Some("k:mammal".to_string()));
+        // Values that already match, operators and unrelated words aren't corrected.
+        assert_eq!(correct("MANTIS", &vocabulary), None);
+        assert_eq!(correct("OR", &vocabulary), None);
+        assert_eq!(correct("c:>2", &vocabulary), None);
+        assert_eq!(correct("zebra", &vocabulary), None);
+        assert_eq!(correct(&"mantis".repeat(20), &vocabulary), None);
+    }
+
+    #[test]
+    fn finding_alternatives() {
+        let cards = cards();
+        let search_cards = normalize::fold_cards(&cards);
+        let vocabulary = Vocabulary::new(&cards);
+        let find = |query: &str, deadline: Instant| -> Vec<(String, usize)> {
+            alternatives(
+                query,
+                &cards,
+                &search_cards,
+                &HashMap::new(),
+                &vocabulary,
+                deadline,
+            )
+            .into_iter()
+            .map(|x| (x.query, x.results))
+            .collect()
+        };
+        let later = Instant::now() + Duration::from_secs(10);
+
+        // Both misspellings corrected at once come first, then each on its own.
+        assert_eq!(
+            find("mnatis k:bugg", later),
+            [("mantis k:bug".to_string(), 1)]
+        );
+        // Terms are dropped when correcting them isn't enough.
+        assert_eq!(
+            find("moth c:3", later),
+            [("c:3".to_string(), 1), ("moth".to_string(), 1)]
+        );
+        assert!(find("zebra", later).is_empty());
+        assert!(find(&"moth ".repeat(20), later).is_empty());
+        // Nothing is searched once the deadline has passed.
+        let earlier = Instant::now().checked_sub(Duration::from_secs(1)).unwrap();
+        assert!(find("moth c:3", earlier).is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpResponse, Responder};
+use hemoglobin::cards::Card;
+
+use crate::rich_text::referenced_ids;
+use crate::AppState;
+
+/// Which cards link to each card in their descriptions, rebuilt from the card map every time it's loaded.
+#[derive(Default)]
+pub struct Backlinks {
+    sources: HashMap<String, Vec<String>>,
+}
+
+impl Backlinks {
+    pub fn new(cards: &HashMap<String, Card>) -> Self {
+        let mut sources: HashMap<String, Vec<String>> = HashMap::new();
+        for card in cards.values() {
+            for id in referenced_ids(&card.description) {
+                if id != card.id {
+                    sources
+                        .entry(id.to_string())
+                        .or_default()
+                        .push(card.id.clone());
+                }
+            }
+        }
+        for ids in sources.values_mut() {
+            ids.sort();
+            ids.dedup();
+        }
+        Self { sources }
+    }
+
+    fn get(&self, id: &str) -> &[String] {
+        self.sources.get(id).map_or(&[], Vec::as_slice)
+    }
+}
+
+pub async fn backlinks(data: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    let cards = data.cards.read().await;
+    if !cards.contains_key(id.as_str()) {
+        return HttpResponse::NotFound().body("Not a valid card ID");
+    }
+
+    let backlinks = data.backlinks.read().await;
+    let mut sources: Vec<&Card> = backlinks
+        .get(&id)
+        .iter()
+        .filter_map(|id| cards.get(id))
+        .collect();
+    sources.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+
+    HttpResponse::Ok().json(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hemoglobin::cards::Card;
+    use serde_json::{json, Value};
+
+    use super::Backlinks;
+
+    fn card(id: &str, description: &Value) -> (String, Card) {
+        // Rich text only deserializes from borrowed strings, so this goes through text.
+        let card = json!({
+            "id": id, "name": id, "description": description, "cost": 1, "health": 1,
+            "defense": 0, "power": 1, "type": "creature", "legality": {},
+        });
+        (
+            id.to_string(),
+            serde_json::from_str(&card.to_string()).unwrap(),
+        )
+    }
+
+    fn link(id: &str) -> Value {
+        json!({"display": id, "id": id})
+    }
+
+    #[test]
+    fn sources() {
+        let cards: HashMap<String, Card> = [
+            // Linking to a card twice only counts once, and links to itself don't count.
+            card("b", &json!([link("a"), " and ", link("a"), link("b")])),
+            card("a", &json!([[[link("c")]]])),
+            card("c", &json!([link("a"), link("missing")])),
+            card("d", &json!("Nothing")),
+        ]
+        .into_iter()
+        .collect();
+        let backlinks = Backlinks::new(&cards);
+
+        assert_eq!(backlinks.get("a"), ["b", "c"]);
+        // Links inside sagas count too.
+        assert_eq!(backlinks.get("c"), ["a"]);
+        assert!(backlinks.get("b").is_empty());
+        assert!(backlinks.get("d").is_empty());
+        // Links to cards that don't exist are kept, in case the card is added later.
+        assert_eq!(backlinks.get("missing"), ["c"]);
+    }
+}
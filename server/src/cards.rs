@@ -31,7 +31,7 @@ pub struct Card {
 }
 
 impl Card {
-    pub fn get_cost(&self) -> usize {
+    pub const fn get_cost(&self) -> usize {
         self.cost
     }
     pub fn get_name(&self) -> &str {
@@ -40,19 +40,22 @@ impl Card {
     pub fn get_type(&self) -> &str {
         &self.r#type
     }
+    pub fn get_description(&self) -> &str {
+        &self.description
+    }
     pub fn get_kins(&self) -> &[String] {
         &self.kins
     }
     pub fn get_keywords(&self) -> &[Keyword] {
         &self.keywords
     }
-    pub fn get_health(&self) -> usize {
+    pub const fn get_health(&self) -> usize {
         self.health
     }
-    pub fn get_power(&self) -> usize {
+    pub const fn get_power(&self) -> usize {
         self.power
     }
-    pub fn get_defense(&self) -> usize {
+    pub const fn get_defense(&self) -> usize {
         self.defense
     }
 }
@@ -85,6 +88,36 @@ pub struct CardID {
     pub functions: Option<Vec<String>>,
 }
 
+impl CardID {
+    /// Checks whether a card fits this description. Names, types and descriptions only have to contain the text, and the card can have more kins, keywords, abilities and functions than these.
+    pub fn describes(&self, card: &Card) -> bool {
+        let contains = |text: &str, part: &Option<String>| {
+            part.as_ref()
+                .is_none_or(|x| text.to_lowercase().contains(&x.to_lowercase()))
+        };
+        let has_all = |values: &[String], parts: &Option<Vec<String>>| {
+            parts
+                .iter()
+                .flatten()
+                .all(|x| values.iter().any(|value| value.eq_ignore_ascii_case(x)))
+        };
+        contains(&card.name, &self.name)
+            && contains(&card.description, &self.description)
+            && contains(&card.r#type, &self.r#type)
+            && has_all(&card.kins, &self.kins)
+            && has_all(&card.abilities, &self.abilities)
+            && has_all(&card.functions, &self.functions)
+            && self.keywords.iter().flatten().all(|x| {
+                card.keywords
+                    .iter()
+                    .any(|keyword| keyword.name.eq_ignore_ascii_case(&x.name))
+            })
+            && self.health.is_none_or(|x| x == card.health)
+            && self.defense.is_none_or(|x| x == card.defense)
+            && self.power.is_none_or(|x| x == card.power)
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(tag = "type")]
 pub enum KeywordData {
@@ -0,0 +1,475 @@
+pub mod export;
+pub mod proxies;
+pub mod stats;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_web::{web, HttpResponse, Responder};
+use hemoglobin::cards::Card;
+use serde::{Deserialize, Serialize};
+
+use crate::formats::{Format, Legality, DEFAULT_COPY_LIMIT};
+use crate::AppState;
+
+/// How similar a line has to be to a card's name for it to match when no name matches exactly.
+const FUZZY_THRESHOLD: f64 = 0.75;
+
+/// How many lines a deck list can have. Lines that don't match a card are compared against every card's name, so this keeps a single list from taking too long.
+const MAX_DECK_LINES: usize = 1000;
+
+const TOO_MANY_COPIES: &str = "Too many copies";
+const NO_COPIES: &str = "A card needs at least one copy";
+
+/// A card in an already resolved deck.
+#[derive(Deserialize)]
+pub struct DeckCard {
+    pub id: String,
+    #[serde(default = "default_count")]
+    pub count: usize,
+    /// Index of the artwork to use for the card.
+    #[serde(default)]
+    pub art: usize,
+}
+
+const fn default_count() -> usize {
+    1
+}
+
+/// Counts the cards in an already resolved deck, or returns `None` if there are more than `limit`. Counts come from the client, so a single huge entry or a sum that would overflow also fails.
+pub fn deck_size(deck: &[DeckCard], limit: usize) -> Option<usize> {
+    deck.iter().try_fold(0, |total: usize, entry| {
+        total
+            .checked_add(entry.count)
+            .filter(|total| *total <= limit)
+    })
+}
+
+/// Looks up every card of an already resolved deck, failing on the first ID that doesn't exist.
+pub fn resolve_deck<'a>(
+    cards: &'a HashMap<String, Card>,
+    deck: &'a [DeckCard],
+) -> Result<Vec<(&'a Card, &'a DeckCard)>, String> {
+    deck.iter()
+        .map(|entry| {
+            cards
+                .get(&entry.id)
+                .map(|card| (card, entry))
+                .ok_or_else(|| format!("Not a valid card ID: {}", entry.id))
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct ValidateParams {
+    format: Option<String>,
+}
+
+/// How a deck list line was matched to a card.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    Id,
+    Name,
+    Fuzzy,
+}
+
+#[derive(Serialize)]
+struct ResolvedLine<'a> {
+    line: usize,
+    text: &'a str,
+    count: usize,
+    matched: MatchKind,
+    card: &'a Card,
+}
+
+#[derive(Serialize)]
+struct UnresolvedLine<'a> {
+    line: usize,
+    text: &'a str,
+}
+
+/// A line that names a card but couldn't be read, such as one with a count that doesn't fit.
+#[derive(Serialize)]
+struct LineError<'a> {
+    line: usize,
+    text: &'a str,
+    message: &'static str,
+}
+
+#[derive(Serialize)]
+struct CopyLimitViolation<'a> {
+    id: &'a str,
+    name: &'a str,
+    count: usize,
+    limit: usize,
+}
+
+#[derive(Serialize)]
+struct LegalityViolation<'a> {
+    id: &'a str,
+    name: &'a str,
+    legality: Legality,
+}
+
+#[derive(Serialize)]
+struct Validation<'a> {
+    valid: bool,
+    format: Option<&'a str>,
+    cards: Vec<ResolvedLine<'a>>,
+    unresolved: Vec<UnresolvedLine<'a>>,
+    errors: Vec<LineError<'a>>,
+    copy_limit_violations: Vec<CopyLimitViolation<'a>>,
+    legality_violations: Vec<LegalityViolation<'a>>,
+}
+
+/// Splits a deck list line into its count and the card it names. Accepts `3 Card`, `3x Card` and `Card x3`. Lines without a count count once.
+fn parse_line(line: &str) -> Result<(usize, &str), &'static str> {
+    if let Some((count, rest)) = line.split_once(char::is_whitespace) {
+        if let Some(count) = parse_count(count.trim_end_matches(['x', 'X'])) {
+            return Ok((count?, rest.trim()));
+        }
+    }
+    if let Some((rest, count)) = line.rsplit_once(char::is_whitespace) {
+        if let Some(count) = count.strip_prefix(['x', 'X']).and_then(parse_count) {
+            return Ok((count?, rest.trim()));
+        }
+    }
+    Ok((1, line))
+}
+
+/// Reads a count, returning `None` if the text isn't a number at all.
+fn parse_count(text: &str) -> Option<Result<usize, &'static str>> {
+    (!text.is_empty() && text.bytes().all(|x| x.is_ascii_digit())).then(|| match text.parse() {
+        Ok(0) => Err(NO_COPIES),
+        Ok(count) => Ok(count),
+        Err(_) => Err(TOO_MANY_COPIES),
+    })
+}
+
+/// Every card's lowercased name, sorted by ID so ties go to the lowest one. Built once per deck list, so names aren't lowercased again for every line.
+struct CardNames<'a> {
+    cards: &'a HashMap<String, Card>,
+    names: Vec<(String, &'a Card)>,
+}
+
+impl<'a> CardNames<'a> {
+    fn new(cards: &'a HashMap<String, Card>) -> Self {
+        let mut names: Vec<(String, &Card)> = cards
+            .values()
+            .map(|card| (card.name.to_lowercase(), card))
+            .collect();
+        names.sort_unstable_by(|(_, a), (_, b)| a.id.cmp(&b.id));
+        Self { cards, names }
+    }
+
+    /// Finds the card a deck list line refers to, trying IDs first, then exact names, then names that are close enough.
+    fn resolve(&self, name: &str) -> Option<(&'a Card, MatchKind)> {
+        if let Some(card) = self.cards.get(name) {
+            return Some((card, MatchKind::Id));
+        }
+
+        let name = name.to_lowercase();
+        if let Some(card) = self.cards.get(&name) {
+            return Some((card, MatchKind::Id));
+        }
+
+        if let Some((_, card)) = self.names.iter().find(|(x, _)| *x == name) {
+            return Some((card, MatchKind::Name));
+        }
+
+        self.names
+            .iter()
+            .map(|(x, card)| (*card, strsim::normalized_damerau_levenshtein(x, &name)))
+            .filter(|(_, similarity)| *similarity >= FUZZY_THRESHOLD)
+            // Earlier cards have lower IDs, so they win ties.
+            .reduce(|best, next| if next.1 > best.1 { next } else { best })
+            .map(|(card, _)| (card, MatchKind::Fuzzy))
+    }
+}
+
+pub async fn validate(
+    data: web::Data<AppState>,
+    params: web::Query<ValidateParams>,
+    body: String,
+) -> impl Responder {
+    let format = match &params.format {
+        Some(name) => match data.formats.read().await.get(name) {
+            Some(format) => Some(format.clone()),
+            None => return HttpResponse::NotFound().body("Not a valid format"),
+        },
+        None => None,
+    };
+    if body.lines().count() > MAX_DECK_LINES {
+        return HttpResponse::BadRequest().body(format!(
+            "Deck lists can have at most {MAX_DECK_LINES} lines"
+        ));
+    }
+
+    // Matching names is slow enough that it shouldn't hold up the async workers.
+    let cards = Arc::clone(&data.cards);
+    let result = web::block(move || {
+        let cards = cards.blocking_read();
+        serde_json::to_string(&validate_deck(&body, format.as_ref(), &cards))
+    })
+    .await;
+    match result {
+        Ok(Ok(json)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json),
+        Ok(Err(x)) => HttpResponse::InternalServerError().body(format!("Couldn't validate: {x}")),
+        Err(x) => HttpResponse::InternalServerError().body(format!("Couldn't validate: {x}")),
+    }
+}
+
+/// Resolves every line of a deck list and checks the deck against a format, or only against the default copy limit if there's none.
+fn validate_deck<'a>(
+    body: &'a str,
+    format: Option<&'a Format>,
+    cards: &'a HashMap<String, Card>,
+) -> Validation<'a> {
+    let names = CardNames::new(cards);
+    let mut resolved = vec![];
+    let mut unresolved = vec![];
+    let mut errors = vec![];
+    let mut totals: Vec<(&Card, usize)> = vec![];
+    // Where each card is in `totals`, by ID.
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    for (idx, text) in body.lines().enumerate() {
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') || text.starts_with("//") {
+            continue;
+        }
+        let (count, name) = match parse_line(text) {
+            Ok(line) => line,
+            Err(message) => {
+                errors.push(LineError {
+                    line: idx + 1,
+                    text,
+                    message,
+                });
+                continue;
+            }
+        };
+        let Some((card, matched)) = names.resolve(name) else {
+            unresolved.push(UnresolvedLine {
+                line: idx + 1,
+                text,
+            });
+            continue;
+        };
+
+        let total = *positions.entry(&card.id).or_insert_with(|| {
+            totals.push((card, 0));
+            totals.len() - 1
+        });
+        let Some(sum) = totals[total].1.checked_add(count) else {
+            errors.push(LineError {
+                line: idx + 1,
+                text,
+                message: TOO_MANY_COPIES,
+            });
+            continue;
+        };
+        totals[total].1 = sum;
+        resolved.push(ResolvedLine {
+            line: idx + 1,
+            text,
+            count,
+            matched,
+            card,
+        });
+    }
+
+    let copy_limit_violations: Vec<CopyLimitViolation> = totals
+        .iter()
+        .filter_map(|(card, count)| {
+            let limit = format.map_or(DEFAULT_COPY_LIMIT, |format| format.copy_limit(card));
+            (*count > limit).then_some(CopyLimitViolation {
+                id: &card.id,
+                name: &card.name,
+                count: *count,
+                limit,
+            })
+        })
+        .collect();
+
+    let legality_violations: Vec<LegalityViolation> = format.map_or_else(Vec::new, |format| {
+        totals
+            .iter()
+            .map(|(card, _)| (card, format.legality(card)))
+            .filter(|(_, legality)| !legality.is_playable())
+            .map(|(card, legality)| LegalityViolation {
+                id: &card.id,
+                name: &card.name,
+                legality,
+            })
+            .collect()
+    });
+
+    Validation {
+        valid: unresolved.is_empty()
+            && errors.is_empty()
+            && copy_limit_violations.is_empty()
+            && legality_violations.is_empty(),
+        format: format.map(|x| x.name.as_str()),
+        cards: resolved,
+        unresolved,
+        errors,
+        copy_limit_violations,
+        legality_violations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hemoglobin::cards::Card;
+    use serde_json::json;
+
+    use super::{
+        deck_size, parse_line, validate_deck, CardNames, DeckCard, MatchKind, NO_COPIES,
+        TOO_MANY_COPIES,
+    };
+
+    fn entry(count: usize) -> DeckCard {
+        DeckCard {
+            id: String::new(),
+            count,
+            art: 0,
+        }
+    }
+
+    fn cards() -> HashMap<String, Card> {
+        [
+            ("vampire_mantis", "Vampire Mantis"),
+            ("moth", "Moth"),
+            ("twin_b", "Twin"),
+            ("twin_a", "Twin"),
+        ]
+        .into_iter()
+        .map(|(id, name)| {
+            let card = json!({
+                "id": id, "name": name, "description": "", "cost": 1, "health": 1,
+                "defense": 0, "power": 1, "type": "creature", "legality": {},
+            });
+            (
+                id.to_string(),
+                serde_json::from_str(&card.to_string()).unwrap(),
+            )
+        })
+        .collect()
+    }
+
+    #[test]
+    fn counts() {
+        assert_eq!(parse_line("3 Vampire Mantis"), Ok((3, "Vampire Mantis")));
+        assert_eq!(parse_line("3x Vampire Mantis"), Ok((3, "Vampire Mantis")));
+        assert_eq!(parse_line("Vampire Mantis x3"), Ok((3, "Vampire Mantis")));
+        assert_eq!(parse_line("Vampire Mantis"), Ok((1, "Vampire Mantis")));
+        assert_eq!(parse_line("2   Mantis  "), Ok((2, "Mantis")));
+    }
+
+    #[test]
+    fn malformed_lines() {
+        // Words that only look like counts are part of the name.
+        assert_eq!(parse_line("x Mantis"), Ok((1, "x Mantis")));
+        assert_eq!(parse_line("Mantis x"), Ok((1, "Mantis x")));
+        assert_eq!(parse_line("3a Mantis"), Ok((1, "3a Mantis")));
+        assert_eq!(parse_line("-3 Mantis"), Ok((1, "-3 Mantis")));
+        assert_eq!(parse_line("3"), Ok((1, "3")));
+        assert_eq!(parse_line("0 Mantis"), Err(NO_COPIES));
+        assert_eq!(parse_line("Mantis x00"), Err(NO_COPIES));
+    }
+
+    #[test]
+    fn count_overflow() {
+        assert_eq!(
+            parse_line("18446744073709551615 Mantis"),
+            Ok((usize::MAX, "Mantis"))
+        );
+        assert_eq!(
+            parse_line("18446744073709551616 Mantis"),
+            Err(TOO_MANY_COPIES)
+        );
+        assert_eq!(
+            parse_line("Mantis x99999999999999999999"),
+            Err(TOO_MANY_COPIES)
+        );
+    }
+
+    #[test]
+    fn deck_sizes() {
+        assert_eq!(deck_size(&[], 10), Some(0));
+        assert_eq!(deck_size(&[entry(3), entry(7)], 10), Some(10));
+        assert_eq!(deck_size(&[entry(3), entry(8)], 10), None);
+        assert_eq!(deck_size(&[entry(11)], 10), None);
+        assert_eq!(deck_size(&[entry(usize::MAX), entry(2)], usize::MAX), None);
+    }
+
+    #[test]
+    fn resolving_cards() {
+        let cards = cards();
+        let names = CardNames::new(&cards);
+        let resolve = |name| {
+            names
+                .resolve(name)
+                .map(|(card, kind)| (card.id.as_str(), kind))
+        };
+
+        assert_eq!(resolve("moth"), Some(("moth", MatchKind::Id)));
+        assert_eq!(resolve("MOTH"), Some(("moth", MatchKind::Id)));
+        assert_eq!(
+            resolve("vampire MANTIS"),
+            Some(("vampire_mantis", MatchKind::Name))
+        );
+        assert_eq!(
+            resolve("Vampyre Mantis"),
+            Some(("vampire_mantis", MatchKind::Fuzzy))
+        );
+        // Cards with the same name resolve to the lowest ID, however they're matched.
+        assert_eq!(resolve("twin"), Some(("twin_a", MatchKind::Name)));
+        assert_eq!(resolve("Twim"), Some(("twin_a", MatchKind::Fuzzy)));
+        assert_eq!(resolve("Wasp"), None);
+    }
+
+    #[test]
+    fn validating_decks() {
+        let cards = cards();
+        let body = "3 Vampire Mantis\n# Sideboard\n\n2 vampire_mantis\nWasp\n0 Moth\nTwin x18446744073709551615\n1 twin_a\nMoth";
+        let validation = validate_deck(body, None, &cards);
+
+        let resolved: Vec<(usize, &str, usize)> = validation
+            .cards
+            .iter()
+            .map(|x| (x.line, x.card.id.as_str(), x.count))
+            .collect();
+        assert_eq!(
+            resolved,
+            [
+                (1, "vampire_mantis", 3),
+                (4, "vampire_mantis", 2),
+                (7, "twin_a", usize::MAX),
+                (9, "moth", 1),
+            ]
+        );
+        assert_eq!(validation.unresolved.len(), 1);
+        assert_eq!(validation.unresolved[0].line, 5);
+        let errors: Vec<(usize, &str)> = validation
+            .errors
+            .iter()
+            .map(|x| (x.line, x.message))
+            .collect();
+        assert_eq!(errors, [(6, NO_COPIES), (8, TOO_MANY_COPIES)]);
+        let violations: Vec<(&str, usize)> = validation
+            .copy_limit_violations
+            .iter()
+            .map(|x| (x.id, x.count))
+            .collect();
+        assert_eq!(violations, [("vampire_mantis", 5), ("twin_a", usize::MAX)]);
+        assert!(!validation.valid);
+
+        assert!(validate_deck("2 Moth\nTwin", None, &cards).valid);
+    }
+}
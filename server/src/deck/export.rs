@@ -0,0 +1,211 @@
+use actix_web::{web, HttpResponse, Responder};
+use hemoglobin::cards::Card;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{deck_size, resolve_deck, DeckCard};
+use crate::{image_file_name, AppState};
+
+/// Used as the back of every card when the request doesn't specify one.
+const DEFAULT_CARD_BACK: &str = "https://file.garden/ZJSEzoaUL3bz8vYK/hemolymphlogo.png";
+/// Every copy of a card is written out separately, so this keeps a single request from building an unreasonably large export.
+const MAX_EXPORTED_CARDS: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct TabletopParams {
+    back: Option<String>,
+    name: Option<String>,
+}
+
+fn too_many_cards() -> HttpResponse {
+    HttpResponse::BadRequest().body(format!(
+        "Exported decks can have at most {MAX_EXPORTED_CARDS} cards"
+    ))
+}
+
+/// Exports a deck as a Marrow deck list, which has one Marrow ID per line for every copy of every card.
+pub async fn marrow(data: web::Data<AppState>, deck: web::Json<Vec<DeckCard>>) -> impl Responder {
+    if deck_size(&deck, MAX_EXPORTED_CARDS).is_none() {
+        return too_many_cards();
+    }
+    let cards = data.cards.read().await;
+    let deck = match resolve_deck(&cards, &deck) {
+        Ok(deck) => deck,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(marrow_list(&deck))
+}
+
+fn marrow_list(deck: &[(&Card, &DeckCard)]) -> String {
+    let mut list = String::new();
+    for (card, entry) in deck {
+        let image = card.get_image_path(entry.art);
+        for _ in 0..entry.count {
+            list.push_str(&image);
+            list.push('\n');
+        }
+    }
+    list
+}
+
+fn tabletop_transform() -> Value {
+    json!({
+        "posX": 0.0, "posY": 1.0, "posZ": 0.0,
+        "rotX": 0.0, "rotY": 180.0, "rotZ": 180.0,
+        "scaleX": 1.0, "scaleY": 1.0, "scaleZ": 1.0
+    })
+}
+
+/// Exports a deck as a Tabletop Simulator saved object. Every card gets its own single-card custom deck, since each one has a separate image.
+pub async fn tabletop(
+    data: web::Data<AppState>,
+    params: web::Query<TabletopParams>,
+    deck: web::Json<Vec<DeckCard>>,
+) -> impl Responder {
+    if deck_size(&deck, MAX_EXPORTED_CARDS).is_none() {
+        return too_many_cards();
+    }
+    let cards = data.cards.read().await;
+    let deck = match resolve_deck(&cards, &deck) {
+        Ok(deck) => deck,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    let object = tabletop_object(
+        &deck,
+        params.back.as_deref().unwrap_or(DEFAULT_CARD_BACK),
+        params.name.as_deref().unwrap_or("Bloodless Deck"),
+        |image| data.images.public_link(&image_file_name(image)),
+    );
+
+    HttpResponse::Ok().json(json!({ "ObjectStates": [object] }))
+}
+
+/// Builds the Tabletop Simulator object for a deck, linking to each card's art with `face_url`. Single cards are exported as a card instead of a deck.
+fn tabletop_object(
+    deck: &[(&Card, &DeckCard)],
+    back: &str,
+    name: &str,
+    face_url: impl Fn(&str) -> String,
+) -> Value {
+    let mut deck_ids = vec![];
+    let mut custom_deck = serde_json::Map::new();
+    let mut contained = vec![];
+    for (idx, (card, entry)) in deck.iter().enumerate() {
+        let key = (idx + 1).to_string();
+        let face = json!({
+            "FaceURL": face_url(&card.get_image_path(entry.art)),
+            "BackURL": back,
+            "NumWidth": 1,
+            "NumHeight": 1,
+            "BackIsHidden": true,
+            "UniqueBack": false,
+            "Type": 0
+        });
+        let card_id = (idx + 1) * 100;
+        for _ in 0..entry.count {
+            deck_ids.push(card_id);
+            contained.push(json!({
+                "Name": "Card",
+                "Nickname": card.name,
+                "Description": card.description.to_string(),
+                "CardID": card_id,
+                "Transform": tabletop_transform(),
+                "CustomDeck": { key.clone(): face.clone() }
+            }));
+        }
+        custom_deck.insert(key, face);
+    }
+
+    if contained.len() == 1 {
+        contained.remove(0)
+    } else {
+        json!({
+            "Name": "DeckCustom",
+            "Nickname": name,
+            "Transform": tabletop_transform(),
+            "DeckIDs": deck_ids,
+            "CustomDeck": custom_deck,
+            "ContainedObjects": contained
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hemoglobin::cards::Card;
+    use serde_json::json;
+
+    use super::{marrow_list, tabletop_object};
+    use crate::deck::DeckCard;
+
+    fn card(id: &str, name: &str) -> Card {
+        let card = json!({
+            "id": id, "name": name, "description": "Eats.", "cost": 1, "health": 1,
+            "defense": 0, "power": 1, "type": "creature", "legality": {},
+        });
+        serde_json::from_str(&card.to_string()).unwrap()
+    }
+
+    fn entry(id: &str, count: usize) -> DeckCard {
+        DeckCard {
+            id: id.to_string(),
+            count,
+            art: 0,
+        }
+    }
+
+    #[test]
+    fn marrow_lists() {
+        let (mantis, moth) = (card("mantis", "Vampire Mantis"), card("moth", "Moth"));
+        let (two, one) = (entry("mantis", 2), entry("moth", 1));
+        assert_eq!(
+            marrow_list(&[(&mantis, &two), (&moth, &one)]),
+            "VampireMantis\nVampireMantis\nMoth\n"
+        );
+        assert_eq!(marrow_list(&[]), "");
+    }
+
+    #[test]
+    fn tabletop_decks() {
+        let (mantis, moth) = (card("mantis", "Vampire Mantis"), card("moth", "Moth"));
+        let (two, one) = (entry("mantis", 2), entry("moth", 1));
+        let object = tabletop_object(&[(&mantis, &two), (&moth, &one)], "back", "Bugs", |x| {
+            format!("https://images/{x}")
+        });
+
+        assert_eq!(object["Name"], "DeckCustom");
+        assert_eq!(object["Nickname"], "Bugs");
+        // Each card is its own custom deck, and its IDs are that deck's key times 100.
+        assert_eq!(object["DeckIDs"], json!([100, 100, 200]));
+        assert_eq!(
+            object["CustomDeck"]["1"]["FaceURL"],
+            "https://images/VampireMantis"
+        );
+        assert_eq!(object["CustomDeck"]["2"]["FaceURL"], "https://images/Moth");
+        assert_eq!(object["CustomDeck"]["2"]["BackURL"], "back");
+        assert_eq!(object["CustomDeck"].as_object().unwrap().len(), 2);
+
+        let contained = object["ContainedObjects"].as_array().unwrap();
+        let ids: Vec<_> = contained.iter().map(|x| &x["CardID"]).collect();
+        assert_eq!(ids, [100, 100, 200]);
+        assert_eq!(contained[2]["Nickname"], "Moth");
+        assert_eq!(contained[2]["Description"], "Eats.");
+        assert_eq!(
+            contained[2]["CustomDeck"],
+            json!({"2": object["CustomDeck"]["2"]})
+        );
+    }
+
+    #[test]
+    fn single_cards() {
+        let moth = card("moth", "Moth");
+        let one = entry("moth", 1);
+        let object = tabletop_object(&[(&moth, &one)], "back", "Bugs", ToString::to_string);
+        assert_eq!(object["Name"], "Card");
+        assert_eq!(object["CardID"], 100);
+        assert_eq!(object["CustomDeck"]["1"]["FaceURL"], "Moth");
+    }
+}
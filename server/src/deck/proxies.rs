@@ -0,0 +1,527 @@
+// Page geometry reads better as plain arithmetic than as chains of `mul_add`.
+#![allow(clippy::suboptimal_flops)]
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use actix_web::http::header;
+use actix_web::{web, Either, HttpResponse, Responder};
+use futures_util::stream::{self, StreamExt};
+use image::RgbImage;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str};
+use serde::Deserialize;
+
+use super::{deck_size, resolve_deck, DeckCard};
+use crate::{image_file_name, AppState};
+
+/// PDF points in a millimeter.
+const MM: f32 = 72.0 / 25.4;
+const CARD_WIDTH: f32 = 63.0;
+const CARD_HEIGHT: f32 = 88.0;
+const COLUMNS: usize = 3;
+const ROWS: usize = 3;
+const MAX_BLEED: f32 = 5.0;
+const CUT_MARK_LENGTH: f32 = 5.0;
+/// Space between the printed cards and the start of the cut marks.
+const CUT_MARK_GAP: f32 = 1.0;
+/// Keeps a single request from rendering an unreasonable amount of pages.
+const MAX_PROXIES: usize = 360;
+/// How many cards' art is looked up at once.
+const CONCURRENT_FETCHES: usize = 8;
+const FONT_NAME: Name = Name(b"F1");
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Paper {
+    #[default]
+    A4,
+    Letter,
+}
+
+impl Paper {
+    /// Width and height in millimeters.
+    const fn size(self) -> (f32, f32) {
+        match self {
+            Self::A4 => (210.0, 297.0),
+            Self::Letter => (215.9, 279.4),
+        }
+    }
+}
+
+/// A deck sent from an HTML form, as the same JSON the endpoint otherwise takes as its body. Lets pages print proxies with a plain form that opens the PDF.
+#[derive(Deserialize)]
+pub struct ProxyForm {
+    deck: String,
+}
+
+#[derive(Deserialize)]
+pub struct ProxyParams {
+    #[serde(default)]
+    paper: Paper,
+    /// Millimeters of art printed past each cut line.
+    #[serde(default)]
+    bleed: f32,
+    #[serde(default)]
+    cut_marks: bool,
+}
+
+/// A card to print, and the art it will be printed with if there is any.
+struct Slot {
+    name: String,
+    image: Option<PathBuf>,
+}
+
+/// Where cards go on every page, in millimeters from the bottom left corner.
+struct Layout {
+    paper: (f32, f32),
+    bleed: f32,
+    left: f32,
+    bottom: f32,
+}
+
+impl Layout {
+    fn new(paper: Paper, bleed: f32) -> Option<Self> {
+        let paper = paper.size();
+        let (grid_width, grid_height) = Self::grid_size(bleed);
+        if !(0.0..=MAX_BLEED).contains(&bleed) || grid_width > paper.0 || grid_height > paper.1 {
+            return None;
+        }
+
+        Some(Self {
+            paper,
+            bleed,
+            left: (paper.0 - grid_width) / 2.0,
+            bottom: (paper.1 - grid_height) / 2.0,
+        })
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn grid_size(bleed: f32) -> (f32, f32) {
+        (
+            (CARD_WIDTH + bleed * 2.0) * COLUMNS as f32,
+            (CARD_HEIGHT + bleed * 2.0) * ROWS as f32,
+        )
+    }
+
+    /// The area a card's art covers, including its bleed.
+    #[allow(clippy::cast_precision_loss)]
+    fn cell(&self, index: usize) -> (f32, f32, f32, f32) {
+        let width = CARD_WIDTH + self.bleed * 2.0;
+        let height = CARD_HEIGHT + self.bleed * 2.0;
+        let column = index % COLUMNS;
+        let row = ROWS - 1 - index / COLUMNS;
+        (
+            self.left + width * column as f32,
+            self.bottom + height * row as f32,
+            width,
+            height,
+        )
+    }
+
+    /// The marks in the margins, lined up with every cut line, as pairs of points in millimeters. There are none if the margins are too small to fit them.
+    #[allow(clippy::cast_precision_loss)]
+    fn cut_lines(&self) -> Vec<[(f32, f32); 2]> {
+        let (grid_width, grid_height) = Self::grid_size(self.bleed);
+        let right = self.left + grid_width;
+        let top = self.bottom + grid_height;
+        let length = CUT_MARK_LENGTH.min(self.left.min(self.bottom) - CUT_MARK_GAP);
+        if length <= 0.0 {
+            return vec![];
+        }
+
+        let mut lines = vec![];
+        for column in 0..COLUMNS {
+            let cell = self.left + (CARD_WIDTH + self.bleed * 2.0) * column as f32;
+            for x in [cell + self.bleed, cell + self.bleed + CARD_WIDTH] {
+                lines.push([(x, top + CUT_MARK_GAP), (x, top + CUT_MARK_GAP + length)]);
+                let start = self.bottom - CUT_MARK_GAP;
+                lines.push([(x, start), (x, start - length)]);
+            }
+        }
+        for row in 0..ROWS {
+            let cell = self.bottom + (CARD_HEIGHT + self.bleed * 2.0) * row as f32;
+            for y in [cell + self.bleed, cell + self.bleed + CARD_HEIGHT] {
+                let start = self.left - CUT_MARK_GAP;
+                lines.push([(start, y), (start - length, y)]);
+                lines.push([
+                    (right + CUT_MARK_GAP, y),
+                    (right + CUT_MARK_GAP + length, y),
+                ]);
+            }
+        }
+        lines
+    }
+
+    fn cut_marks(&self, content: &mut Content) {
+        let lines = self.cut_lines();
+        if lines.is_empty() {
+            return;
+        }
+        content.set_line_width(0.25);
+        content.set_stroke_gray(0.0);
+        for [from, to] in lines {
+            content.move_to(from.0 * MM, from.1 * MM);
+            content.line_to(to.0 * MM, to.1 * MM);
+        }
+        content.stroke();
+    }
+}
+
+/// The size of a card's art once its edges are extended by `pad` pixels on each side to cover the bleed.
+#[derive(Clone, Copy)]
+struct Art {
+    width: u32,
+    height: u32,
+    pad: (u32, u32),
+}
+
+impl Art {
+    /// Where the art is drawn in a cell, as a PDF transform. The art inside the padding is printed at exactly the card's size, between the cut lines.
+    #[allow(clippy::cast_precision_loss)]
+    fn transform(&self, cell: (f32, f32, f32, f32), bleed: f32) -> [f32; 6] {
+        // Millimeters per pixel.
+        let scale_x = CARD_WIDTH / (self.width - self.pad.0 * 2) as f32;
+        let scale_y = CARD_HEIGHT / (self.height - self.pad.1 * 2) as f32;
+        [
+            self.width as f32 * scale_x * MM,
+            0.0,
+            0.0,
+            self.height as f32 * scale_y * MM,
+            (cell.0 + bleed - self.pad.0 as f32 * scale_x) * MM,
+            (cell.1 + bleed - self.pad.1 as f32 * scale_y) * MM,
+        ]
+    }
+}
+
+/// How many pixels an image needs on each side to cover `bleed` millimeters once it's printed at card size. Rounded up, since art that's clipped at the edge of the cell looks better than paper that shows through.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn bleed_pixels(width: u32, height: u32, bleed: f32) -> (u32, u32) {
+    let pixels = |size: u32, card: f32| (bleed * size as f32 / card).ceil() as u32;
+    (pixels(width, CARD_WIDTH), pixels(height, CARD_HEIGHT))
+}
+
+/// Repeats an image's outermost pixels `pad` times on each side, so the bleed continues the card's border instead of the art being stretched over it.
+fn extend_edges(image: &RgbImage, pad: (u32, u32)) -> RgbImage {
+    RgbImage::from_fn(
+        image.width() + pad.0 * 2,
+        image.height() + pad.1 * 2,
+        |x, y| {
+            let x = x.saturating_sub(pad.0).min(image.width() - 1);
+            let y = y.saturating_sub(pad.1).min(image.height() - 1);
+            *image.get_pixel(x, y)
+        },
+    )
+}
+
+/// Reads a card's art and re-encodes it as JPEG, which PDFs can embed directly, extending its edges to cover the bleed.
+fn load_art(path: &Path, bleed: f32) -> Result<(Vec<u8>, Art), image::ImageError> {
+    let image = image::open(path)?.into_rgb8();
+    if image.width() == 0 || image.height() == 0 {
+        return Err(image::ImageError::Limits(
+            image::error::LimitError::from_kind(image::error::LimitErrorKind::DimensionError),
+        ));
+    }
+    let pad = bleed_pixels(image.width(), image.height(), bleed);
+    let image = extend_edges(&image, pad);
+    let mut jpeg = vec![];
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90).encode_image(&image)?;
+    let art = Art {
+        width: image.width(),
+        height: image.height(),
+        pad,
+    };
+    Ok((jpeg, art))
+}
+
+/// Cards without art are printed as an outline with their name, so they can still be sleeved in front of another card.
+fn placeholder(content: &mut Content, cell: (f32, f32, f32, f32), bleed: f32, name: &str) {
+    let (x, y) = ((cell.0 + bleed) * MM, (cell.1 + bleed) * MM);
+    content.set_line_width(1.0);
+    content.set_stroke_gray(0.0);
+    content.rect(x, y, CARD_WIDTH * MM, CARD_HEIGHT * MM);
+    content.stroke();
+
+    let name: String = name
+        .chars()
+        .map(|x| if x.is_ascii() { x } else { '?' })
+        .collect();
+    content.begin_text();
+    content.set_font(FONT_NAME, 10.0);
+    content.next_line(x + 4.0 * MM, y + (CARD_HEIGHT - 8.0) * MM);
+    content.show(Str(name.as_bytes()));
+    content.end_text();
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn build_pdf(slots: &[Slot], layout: &Layout, cut_marks: bool) -> Vec<u8> {
+    let mut pdf = Pdf::new();
+    let mut next_id = Ref::new(1);
+    let mut alloc = || next_id.bump();
+    let catalog_id = alloc();
+    let page_tree_id = alloc();
+    let font_id = alloc();
+
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.type1_font(font_id).base_font(Name(b"Helvetica"));
+
+    let mut images: HashMap<&Path, Option<(Ref, String, Art)>> = HashMap::new();
+    let mut page_ids = vec![];
+    for page in slots.chunks(COLUMNS * ROWS) {
+        let page_id = alloc();
+        let content_id = alloc();
+        page_ids.push(page_id);
+
+        let mut content = Content::new();
+        let mut used_images = vec![];
+        for (idx, slot) in page.iter().enumerate() {
+            let cell = layout.cell(idx);
+            let image = slot.image.as_deref().and_then(|path| {
+                images
+                    .entry(path)
+                    .or_insert_with(|| match load_art(path, layout.bleed) {
+                        Ok((jpeg, art)) => {
+                            let image_id = alloc();
+                            let mut image = pdf.image_xobject(image_id, &jpeg);
+                            image.filter(Filter::DctDecode);
+                            image.width(art.width as i32);
+                            image.height(art.height as i32);
+                            image.color_space().device_rgb();
+                            image.bits_per_component(8);
+                            image.finish();
+                            Some((image_id, format!("Im{}", image_id.get()), art))
+                        }
+                        Err(x) => {
+                            eprintln!("Failed to read {} for a proxy: {x}", path.display());
+                            None
+                        }
+                    })
+                    .clone()
+            });
+
+            match image {
+                Some((image_id, name, art)) => {
+                    content.save_state();
+                    // Bleed rounded up to whole pixels can reach past the cell, into the next card's.
+                    content.rect(cell.0 * MM, cell.1 * MM, cell.2 * MM, cell.3 * MM);
+                    content.clip_nonzero();
+                    content.end_path();
+                    content.transform(art.transform(cell, layout.bleed));
+                    content.x_object(Name(name.as_bytes()));
+                    content.restore_state();
+                    used_images.push((image_id, name));
+                }
+                None => placeholder(&mut content, cell, layout.bleed, &slot.name),
+            }
+        }
+        if cut_marks {
+            layout.cut_marks(&mut content);
+        }
+        pdf.stream(content_id, &content.finish());
+
+        let mut page = pdf.page(page_id);
+        page.media_box(Rect::new(
+            0.0,
+            0.0,
+            layout.paper.0 * MM,
+            layout.paper.1 * MM,
+        ));
+        page.parent(page_tree_id);
+        page.contents(content_id);
+        let mut resources = page.resources();
+        resources.fonts().pair(FONT_NAME, font_id);
+        let mut x_objects = resources.x_objects();
+        for (image_id, name) in &used_images {
+            x_objects.pair(Name(name.as_bytes()), *image_id);
+        }
+        x_objects.finish();
+        resources.finish();
+        page.finish();
+    }
+
+    let count = i32::try_from(page_ids.len()).unwrap_or(i32::MAX);
+    pdf.pages(page_tree_id).kids(page_ids).count(count);
+    pdf.finish()
+}
+
+/// Renders a deck as 3x3 sheets of true-size cards, ready to be printed and cut. The deck is sent as JSON, or as a form with the JSON in its `deck` field.
+pub async fn proxy_sheet(
+    data: web::Data<AppState>,
+    params: web::Query<ProxyParams>,
+    deck: Either<web::Json<Vec<DeckCard>>, web::Form<ProxyForm>>,
+) -> impl Responder {
+    let deck: Vec<DeckCard> = match deck {
+        Either::Left(deck) => deck.into_inner(),
+        Either::Right(form) => match serde_json::from_str(&form.deck) {
+            Ok(deck) => deck,
+            Err(x) => return HttpResponse::BadRequest().body(format!("Not a valid deck: {x}")),
+        },
+    };
+    let Some(layout) = Layout::new(params.paper, params.bleed) else {
+        return HttpResponse::BadRequest().body("The bleed doesn't fit in the paper size");
+    };
+
+    if deck_size(&deck, MAX_PROXIES).is_none_or(|total| total == 0) {
+        return HttpResponse::BadRequest().body(format!(
+            "Proxy sheets must have between 1 and {MAX_PROXIES} cards"
+        ));
+    }
+
+    // The art is fetched after the card lock is released, since fetching can wait on the upstream.
+    let entries: Vec<(String, String, usize)> = {
+        let cards = data.cards.read().await;
+        let deck = match resolve_deck(&cards, &deck) {
+            Ok(deck) => deck,
+            Err(message) => return HttpResponse::BadRequest().body(message),
+        };
+        deck.into_iter()
+            .map(|(card, entry)| {
+                let image = image_file_name(&card.get_image_path(entry.art));
+                (card.name.clone(), image, entry.count)
+            })
+            .collect()
+    };
+
+    // Art that's already cached is used without revalidating it, so sheets print offline.
+    let images = &data.images;
+    let images: Vec<Option<PathBuf>> = stream::iter(&entries)
+        .map(|(name, image, _)| async move {
+            match images.cached_path(image).await {
+                Ok(path) => Some(path),
+                Err(x) => {
+                    eprintln!("No art for {name} in a proxy sheet: {x:?}");
+                    None
+                }
+            }
+        })
+        .buffered(CONCURRENT_FETCHES)
+        .collect()
+        .await;
+
+    let mut slots = vec![];
+    for ((name, _, count), image) in entries.into_iter().zip(images) {
+        for _ in 0..count {
+            slots.push(Slot {
+                name: name.clone(),
+                image: image.clone(),
+            });
+        }
+    }
+
+    let cut_marks = params.cut_marks;
+    match web::block(move || build_pdf(&slots, &layout, cut_marks)).await {
+        Ok(pdf) => HttpResponse::Ok()
+            .content_type("application/pdf")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                "inline; filename=\"proxies.pdf\"",
+            ))
+            .body(pdf),
+        Err(x) => {
+            HttpResponse::InternalServerError().body(format!("Couldn't render the sheet: {x}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::{bleed_pixels, extend_edges, Art, Layout, Paper, MM};
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.001
+    }
+
+    #[test]
+    fn layouts() {
+        let layout = Layout::new(Paper::A4, 0.0).unwrap();
+        assert_eq!((layout.left, layout.bottom), (10.5, 16.5));
+        // Cards are laid out left to right, from the top row down.
+        assert_eq!(layout.cell(0), (10.5, 192.5, 63.0, 88.0));
+        assert_eq!(layout.cell(4), (73.5, 104.5, 63.0, 88.0));
+        assert_eq!(layout.cell(8), (136.5, 16.5, 63.0, 88.0));
+
+        let layout = Layout::new(Paper::A4, 2.0).unwrap();
+        assert_eq!((layout.left, layout.bottom), (4.5, 10.5));
+        assert_eq!(layout.cell(1), (71.5, 194.5, 67.0, 92.0));
+
+        assert!(Layout::new(Paper::A4, 3.0).is_some());
+        assert!(Layout::new(Paper::Letter, 3.0).is_none());
+        assert!(Layout::new(Paper::A4, 5.5).is_none());
+        assert!(Layout::new(Paper::A4, -1.0).is_none());
+    }
+
+    #[test]
+    fn cut_marks() {
+        let layout = Layout::new(Paper::A4, 2.0).unwrap();
+        let lines = layout.cut_lines();
+        assert_eq!(lines.len(), 24);
+        // Every mark lines up with a cut line, which is the edge of a card inside its bleed.
+        let mut xs: Vec<f32> = lines
+            .iter()
+            .filter(|[from, to]| close(from.0, to.0))
+            .map(|[from, _]| from.0)
+            .collect();
+        xs.sort_by(f32::total_cmp);
+        xs.dedup();
+        assert_eq!(xs, [6.5, 69.5, 73.5, 136.5, 140.5, 203.5]);
+        let mut ys: Vec<f32> = lines
+            .iter()
+            .filter(|[from, to]| close(from.1, to.1))
+            .map(|[from, _]| from.1)
+            .collect();
+        ys.sort_by(f32::total_cmp);
+        ys.dedup();
+        assert_eq!(ys, [12.5, 100.5, 104.5, 192.5, 196.5, 284.5]);
+        // Marks stay in the margins, a gap away from the cards, and are shortened to fit.
+        for [from, to] in &lines {
+            let length = (to.0 - from.0).abs() + (to.1 - from.1).abs();
+            assert!(close(length, 3.5));
+            for (x, y) in [from, to] {
+                assert!((0.0..=210.0).contains(x) && (0.0..=297.0).contains(y));
+                assert!(!(4.5..=205.5).contains(x) || !(10.5..=286.5).contains(y));
+            }
+        }
+
+        // At 3mm of bleed, A4 only has a 1.5mm margin left for marks, and at 3.5mm it has none.
+        assert_eq!(Layout::new(Paper::A4, 3.0).unwrap().cut_lines().len(), 24);
+        assert!(Layout::new(Paper::A4, 3.5).is_some_and(|x| x.cut_lines().is_empty()));
+    }
+
+    #[test]
+    fn bleed() {
+        assert_eq!(bleed_pixels(630, 880, 0.0), (0, 0));
+        assert_eq!(bleed_pixels(630, 880, 2.0), (20, 20));
+        assert_eq!(bleed_pixels(100, 100, 1.0), (2, 2));
+
+        let (a, b) = (Rgb([255, 0, 0]), Rgb([0, 0, 255]));
+        let image = RgbImage::from_fn(2, 1, |x, _| if x == 0 { a } else { b });
+        let extended = extend_edges(&image, (1, 1));
+        assert_eq!(extended.dimensions(), (4, 3));
+        for y in 0..3 {
+            let row: Vec<Rgb<u8>> = (0..4).map(|x| *extended.get_pixel(x, y)).collect();
+            assert_eq!(row, [a, a, b, b]);
+        }
+    }
+
+    #[test]
+    fn art_fits_the_cut_lines() {
+        let art = Art {
+            width: 670,
+            height: 920,
+            pad: (20, 20),
+        };
+        let [width, _, _, height, x, y] = art.transform((10.0, 20.0, 67.0, 92.0), 2.0);
+        // The art without its padding covers exactly the card, at its real size and aspect ratio.
+        let scale = (width / MM / 670.0, height / MM / 920.0);
+        assert!(close(scale.0 * 630.0, 63.0) && close(scale.1 * 880.0, 88.0));
+        assert!(close(x / MM + 20.0 * scale.0, 12.0));
+        assert!(close(y / MM + 20.0 * scale.1, 22.0));
+        // The padding covers the bleed.
+        assert!(x / MM <= 10.0 && y / MM <= 20.0);
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+
+use actix_web::{web, HttpResponse, Responder};
+use hemoglobin::cards::properties::{Number, Read};
+use hemoglobin::cards::Card;
+use hemoglobin::numbers::{ImpreciseOrd, MaybeImprecise, MaybeVar};
+use serde::Serialize;
+
+use super::DeckCard;
+use crate::rich_text::referenced_ids;
+use crate::AppState;
+
+#[derive(Serialize)]
+struct CostCount<'a> {
+    cost: &'a MaybeImprecise,
+    count: usize,
+}
+
+/// A card in the deck whose description links to another card in the deck.
+#[derive(Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Reference<'a> {
+    from: &'a str,
+    to: &'a str,
+}
+
+#[derive(Serialize)]
+struct DeckStats<'a> {
+    total: usize,
+    cost_curve: Vec<CostCount<'a>>,
+    types: BTreeMap<&'a str, usize>,
+    kins: BTreeMap<&'a str, usize>,
+    keywords: BTreeMap<&'a str, usize>,
+    average_health: Option<f64>,
+    average_defense: Option<f64>,
+    average_power: Option<f64>,
+    references: Vec<Reference<'a>>,
+    unresolved: Vec<&'a str>,
+}
+
+/// Finds every link from a card in the deck to another card in the deck, once per pair of cards.
+fn references<'a>(cards: &[(&'a Card, usize)]) -> Vec<Reference<'a>> {
+    let mut references: Vec<Reference> = cards
+        .iter()
+        .flat_map(|(card, _)| {
+            referenced_ids(&card.description)
+                .into_iter()
+                .filter(|id| *id != card.id && cards.iter().any(|(x, _)| x.id == *id))
+                .map(|id| Reference {
+                    from: &card.id,
+                    to: id,
+                })
+        })
+        .collect();
+    references.sort_unstable();
+    references.dedup();
+    references
+}
+
+/// Averages a stat over the cards that have it, weighted by how many copies of each card there are. Variable and imprecise stats are skipped.
+/// The sums are kept as floats, since counts come from the client and can be large enough to overflow.
+#[allow(clippy::cast_precision_loss)]
+fn average(cards: &[(&Card, usize)], property: Number) -> Option<f64> {
+    let (sum, count) = cards
+        .iter()
+        .filter_map(|(card, count)| match card.get_num_property(&property) {
+            Some(MaybeImprecise::Precise(MaybeVar::Const(value))) => {
+                Some((value as f64 * *count as f64, *count as f64))
+            }
+            _ => None,
+        })
+        .fold((0.0, 0.0), |(sum, total), (value, count)| {
+            (sum + value, total + count)
+        });
+
+    (count > 0.0).then(|| sum / count)
+}
+
+pub async fn deck_stats(
+    data: web::Data<AppState>,
+    deck: web::Json<Vec<DeckCard>>,
+) -> impl Responder {
+    let cards = data.cards.read().await;
+
+    let mut resolved: Vec<(&Card, usize)> = vec![];
+    let mut unresolved = vec![];
+    for entry in deck.iter() {
+        match cards.get(&entry.id) {
+            Some(card) => match resolved.iter_mut().find(|(x, _)| x.id == card.id) {
+                Some((_, count)) => *count = count.saturating_add(entry.count),
+                None => resolved.push((card, entry.count)),
+            },
+            None => unresolved.push(entry.id.as_str()),
+        }
+    }
+
+    let mut cost_curve: Vec<CostCount> = vec![];
+    let mut types = BTreeMap::new();
+    let mut kins = BTreeMap::new();
+    let mut keywords = BTreeMap::new();
+    for (card, count) in &resolved {
+        match cost_curve.iter_mut().find(|x| *x.cost == card.cost) {
+            Some(point) => point.count = point.count.saturating_add(*count),
+            None => cost_curve.push(CostCount {
+                cost: &card.cost,
+                count: *count,
+            }),
+        }
+        let total: &mut usize = types.entry(card.r#type.as_str()).or_default();
+        *total = total.saturating_add(*count);
+        for kin in &card.kins {
+            let total: &mut usize = kins.entry(kin.as_str()).or_default();
+            *total = total.saturating_add(*count);
+        }
+        for keyword in &card.keywords {
+            let total: &mut usize = keywords.entry(keyword.name.as_str()).or_default();
+            *total = total.saturating_add(*count);
+        }
+    }
+    cost_curve.sort_by(|a, b| a.cost.imprecise_cmp(b.cost));
+
+    HttpResponse::Ok().json(DeckStats {
+        total: resolved
+            .iter()
+            .fold(0, |total: usize, (_, count)| total.saturating_add(*count)),
+        cost_curve,
+        types,
+        kins,
+        keywords,
+        average_health: average(&resolved, Number::Health),
+        average_defense: average(&resolved, Number::Defense),
+        average_power: average(&resolved, Number::Power),
+        references: references(&resolved),
+        unresolved,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hemoglobin::cards::properties::Number;
+    use hemoglobin::cards::Card;
+    use serde_json::json;
+
+    use super::{average, references, Reference};
+
+    fn card(id: &str, health: usize, description: &serde_json::Value) -> Card {
+        // Rich text only deserializes from borrowed strings, so this goes through text.
+        let card = json!({
+            "id": id, "name": id, "description": description, "cost": 1, "health": health,
+            "defense": 0, "power": 1, "type": "creature", "legality": {},
+        });
+        serde_json::from_str(&card.to_string()).unwrap()
+    }
+
+    fn link(id: &str) -> serde_json::Value {
+        json!({"display": id, "id": id})
+    }
+
+    #[test]
+    fn references_are_deduplicated() {
+        let a = card(
+            "a",
+            1,
+            &json!(["Eats ", link("b"), " and ", link("c"), ", then ", link("b")]),
+        );
+        let b = card("b", 1, &json!([link("a"), link("b"), link("elsewhere")]));
+        let c = card("c", 1, &json!("Nothing"));
+        let deck = [(&a, 1), (&b, 2), (&c, 1)];
+
+        assert_eq!(
+            references(&deck),
+            [
+                Reference { from: "a", to: "b" },
+                Reference { from: "a", to: "c" },
+                Reference { from: "b", to: "a" },
+            ]
+        );
+    }
+
+    #[test]
+    fn averages_of_huge_decks() {
+        let a = card("a", 2, &json!(""));
+        let b = card("b", 4, &json!(""));
+        assert_eq!(average(&[(&a, 1), (&b, 3)], Number::Health), Some(3.5));
+        assert_eq!(
+            average(&[(&a, usize::MAX), (&b, usize::MAX)], Number::Health),
+            Some(3.0)
+        );
+        assert_eq!(average(&[], Number::Health), None);
+    }
+}
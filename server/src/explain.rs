@@ -0,0 +1,719 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpResponse, Responder};
+use hemoglobin::cards::Card;
+use hemoglobin::numbers::Comparison;
+use hemoglobin::search::query_parser::{get_property_from_name, query_parser, Properties};
+use hemoglobin::search::{Query, QueryRestriction, Sort};
+use serde::{Deserialize, Serialize};
+
+use crate::sorting::{self, AppliedSort};
+use crate::{aliases, matching, AppState};
+
+/// How long explaining a query can take before it's stopped. Every node is counted with a search of its own, so large queries can take a while.
+const EXPLAIN_TIME_LIMIT: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+pub struct ExplainParams {
+    #[serde(default)]
+    query: String,
+}
+
+/// Where a node was written in the query, as byte offsets.
+#[derive(Serialize, Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+}
+
+impl Span {
+    const fn to(self, other: Self) -> Self {
+        Self {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+/// A node of the parsed query.
+#[derive(Serialize)]
+struct Node {
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operator: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    span: Span,
+    /// How many cards match this node on its own. Sort directives don't match cards, so they have no count.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matches: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<Self>,
+}
+
+impl Node {
+    const fn new(kind: &'static str, span: Span) -> Self {
+        Self {
+            kind,
+            field: None,
+            operator: None,
+            value: None,
+            span,
+            matches: None,
+            children: vec![],
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Explanation {
+    query_text: String,
+    /// The query with its `@aliases` expanded, if it had any. Spans are positions in this query instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expanded_query: Option<String>,
+    tree: Node,
+    sort: Vec<AppliedSort>,
+}
+
+/// A term of the query and where it was written. `query_parser` doesn't keep track of where things are, so queries are tokenized again the same way it does.
+struct Term {
+    kind: TermKind,
+    span: Span,
+}
+
+enum TermKind {
+    Word(String),
+    Param(String, String),
+    Regex,
+    /// Every word of a query, which `query_parser` turns into a single fuzzy restriction. Words are tokenized one at a time, so this is only made when pairing them with that restriction.
+    Words,
+    /// A group or a `devours` subquery.
+    Nested(Vec<Term>),
+    Not(Box<Term>),
+    LenientNot(Box<Term>),
+    Or(Box<Term>, Option<Box<Term>>),
+    Xor(Box<Term>, Option<Box<Term>>),
+}
+
+enum Mode {
+    Word,
+    Param(String),
+    Quoted(String),
+    Regex,
+    Nested,
+}
+
+#[derive(Default)]
+struct Terms {
+    terms: Vec<Term>,
+}
+
+impl Terms {
+    /// Adds a term, completing an `OR` or `XOR` that's waiting for its second half.
+    fn push(&mut self, term: Term) {
+        match self.terms.pop() {
+            Some(Term {
+                kind: TermKind::Or(a, None),
+                span,
+            }) => self.terms.push(Term {
+                span: span.to(term.span),
+                kind: TermKind::Or(a, Some(Box::new(term))),
+            }),
+            Some(Term {
+                kind: TermKind::Xor(a, None),
+                span,
+            }) => self.terms.push(Term {
+                span: span.to(term.span),
+                kind: TermKind::Xor(a, Some(Box::new(term))),
+            }),
+            Some(last) => {
+                self.terms.push(last);
+                self.terms.push(term);
+            }
+            None => self.terms.push(term),
+        }
+    }
+
+    fn push_negated(&mut self, kind: TermKind, span: Span, negations: usize) {
+        let term = Term {
+            kind,
+            span: Span {
+                start: span.start + negations,
+                end: span.end,
+            },
+        };
+        let kind = match negations {
+            0 => return self.push(term),
+            1 => TermKind::Not(Box::new(term)),
+            _ => TermKind::LenientNot(Box::new(term)),
+        };
+        self.push(Term { kind, span });
+    }
+}
+
+/// Splits a query that `query_parser` accepted into terms. `offset` is where `query` starts in the whole query.
+fn tokenize(query: &str, offset: usize) -> Vec<Term> {
+    let mut terms = Terms::default();
+    let mut mode = Mode::Word;
+    let mut word = String::new();
+    // Where the current term starts, including its negations.
+    let mut start = None;
+    let mut negations = 0;
+    let mut inner_start = 0;
+    let mut depth = 0_usize;
+
+    let chars = query
+        .char_indices()
+        .map(|(idx, ch)| (idx, Some(ch)))
+        .chain([(query.len(), None)]);
+    for (idx, ch) in chars {
+        let span = |end| Span {
+            start: start.unwrap_or(offset + idx),
+            end,
+        };
+        match (&mode, ch) {
+            (Mode::Word, Some(' ') | None) => {
+                match word.as_str() {
+                    "" => (),
+                    "OR" | "XOR" => {
+                        if let Some(term) = terms.terms.pop() {
+                            let span = term.span;
+                            let kind = if word == "OR" {
+                                TermKind::Or(Box::new(term), None)
+                            } else {
+                                TermKind::Xor(Box::new(term), None)
+                            };
+                            terms.terms.push(Term { kind, span });
+                        }
+                    }
+                    _ => terms.push_negated(
+                        TermKind::Word(word.clone()),
+                        span(offset + idx),
+                        negations,
+                    ),
+                }
+                word.clear();
+                start = None;
+                negations = 0;
+            }
+            (Mode::Word, Some(ch)) => {
+                start.get_or_insert(offset + idx);
+                match ch {
+                    '-' => negations += 1,
+                    '(' if word.is_empty() => {
+                        mode = Mode::Nested;
+                        inner_start = idx + 1;
+                    }
+                    ':' => mode = Mode::Param(std::mem::take(&mut word)),
+                    '<' | '!' | '>' | '=' => {
+                        mode = Mode::Param(std::mem::take(&mut word));
+                        word.push(ch);
+                    }
+                    ch => word.push(ch),
+                }
+            }
+            (Mode::Param(name), Some(' ') | None) => {
+                let kind = TermKind::Param(name.clone(), std::mem::take(&mut word));
+                terms.push_negated(kind, span(offset + idx), negations);
+                (mode, start, negations) = (Mode::Word, None, 0);
+            }
+            (Mode::Param(name), Some('"')) if word.is_empty() => {
+                mode = Mode::Quoted(name.clone());
+            }
+            (Mode::Param(_), Some('/')) if word.is_empty() => mode = Mode::Regex,
+            (Mode::Param(_), Some('(')) if word.is_empty() => {
+                mode = Mode::Nested;
+                inner_start = idx + 1;
+            }
+            (Mode::Quoted(name), Some('"')) => {
+                let kind = TermKind::Param(name.clone(), std::mem::take(&mut word));
+                terms.push_negated(kind, span(offset + idx + 1), negations);
+                (mode, start, negations) = (Mode::Word, None, 0);
+            }
+            (Mode::Regex, Some('/') | None) => {
+                let end = offset + idx + usize::from(ch.is_some());
+                terms.push_negated(TermKind::Regex, span(end), negations);
+                word.clear();
+                (mode, start, negations) = (Mode::Word, None, 0);
+            }
+            (Mode::Nested, Some(')')) if depth == 0 => {
+                let inner = tokenize(&query[inner_start..idx], offset + inner_start);
+                terms.push_negated(TermKind::Nested(inner), span(offset + idx + 1), negations);
+                (mode, start, negations) = (Mode::Word, None, 0);
+            }
+            (Mode::Nested, Some(ch)) => match ch {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => (),
+            },
+            (Mode::Param(_) | Mode::Quoted(_) | Mode::Regex, Some(ch)) => word.push(ch),
+            (Mode::Quoted(_) | Mode::Nested, None) => (),
+        }
+    }
+    terms.terms
+}
+
+struct Explainer<'a> {
+    cards: &'a HashMap<String, Card>,
+    search_cards: &'a HashMap<String, Card>,
+    deadline: Instant,
+    /// Whether a count ran past the deadline. Once one has, nothing else is counted, so the explanation can't be trusted and is thrown away.
+    expired: Cell<bool>,
+}
+
+impl Explainer<'_> {
+    fn count(&self, query: &Query) -> usize {
+        if self.expired.get() {
+            return 0;
+        }
+        matching::search_until(
+            query,
+            &self.search_cards.values(),
+            self.cards,
+            self.deadline,
+        )
+        .map_or_else(
+            || {
+                self.expired.set(true);
+                0
+            },
+            |x| x.len(),
+        )
+    }
+
+    fn count_restriction(&self, restriction: &QueryRestriction) -> usize {
+        self.count(&Query {
+            name: String::new(),
+            restrictions: vec![restriction.clone()],
+            sort: Sort::None,
+        })
+    }
+
+    /// A node for a whole query, like a side of an `OR`.
+    fn query(&self, terms: &[Term], query: &Query, span: Span) -> Node {
+        let mut node = Node::new("query", span);
+        node.matches = Some(self.count(&Query {
+            sort: Sort::None,
+            ..query.clone()
+        }));
+        node.children = self.restrictions(terms, query);
+        node
+    }
+
+    /// Pairs terms with the restrictions `query_parser` made from them. Restrictions are made in the same order as the terms, except for words, which all become one fuzzy restriction at the end.
+    fn restrictions(&self, terms: &[Term], query: &Query) -> Vec<Node> {
+        let mut restrictions = query.restrictions.iter();
+        let mut nodes = vec![];
+        let mut words = vec![];
+        for term in terms {
+            match &term.kind {
+                TermKind::Word(word) => {
+                    let mut node = Node::new("word", term.span);
+                    node.value = Some(word.clone());
+                    node.matches =
+                        Some(self.count_restriction(&QueryRestriction::Fuzzy(word.clone())));
+                    words.push(node);
+                }
+                TermKind::Param(name, value) => match get_property_from_name(name) {
+                    Ok(Properties::Sort(order)) => {
+                        let mut node = Node::new("sort", term.span);
+                        node.field = match get_property_from_name(value) {
+                            Ok(Properties::NumProperty(property)) => Some(property.to_string()),
+                            Ok(Properties::StringProperty(property)) => Some(property.to_string()),
+                            _ => None,
+                        };
+                        node.value = Some(order.to_string());
+                        nodes.push(node);
+                    }
+                    _ => nodes.extend(restrictions.next().map(|x| self.restriction(term, x))),
+                },
+                _ => nodes.extend(restrictions.next().map(|x| self.restriction(term, x))),
+            }
+        }
+        if let (Some(first), Some(last), Some(restriction)) =
+            (words.first(), words.last(), restrictions.next())
+        {
+            // Words don't have to be next to each other, so this spans everything between the first and the last one.
+            let mut node = self.restriction(
+                &Term {
+                    kind: TermKind::Words,
+                    span: first.span.to(last.span),
+                },
+                restriction,
+            );
+            node.children = words;
+            nodes.push(node);
+        }
+        nodes
+    }
+
+    fn restriction(&self, term: &Term, restriction: &QueryRestriction) -> Node {
+        let kind = match restriction {
+            QueryRestriction::Fuzzy(_) => "fuzzy",
+            QueryRestriction::Devours(_) => "devours",
+            QueryRestriction::DevouredBy(_) => "devouredby",
+            QueryRestriction::Comparison(..) => "comparison",
+            QueryRestriction::Contains(..) => "contains",
+            QueryRestriction::Regex(..) => "regex",
+            QueryRestriction::Has(..) => "has",
+            QueryRestriction::HasKw(_) => "keyword",
+            QueryRestriction::Not(_) => "not",
+            QueryRestriction::LenientNot(_) => "lenient_not",
+            QueryRestriction::Group(_) => "group",
+            QueryRestriction::Or(..) => "or",
+            QueryRestriction::Xor(..) => "xor",
+        };
+        let mut node = Node::new(kind, term.span);
+        node.matches = Some(self.count_restriction(restriction));
+
+        match (restriction, &term.kind) {
+            (QueryRestriction::Fuzzy(value) | QueryRestriction::HasKw(value), _) => {
+                node.value = Some(value.clone());
+            }
+            (QueryRestriction::Comparison(property, comparison), _) => {
+                let (operator, value) = match comparison {
+                    Comparison::GreaterThan(value) => (">", value),
+                    Comparison::GreaterThanOrEqual(value) => (">=", value),
+                    Comparison::LowerThanOrEqual(value) => ("<=", value),
+                    Comparison::Equal(value) => ("=", value),
+                    Comparison::LowerThan(value) => ("<", value),
+                    Comparison::NotEqual(value) => ("!=", value),
+                };
+                node.field = Some(property.to_string());
+                node.operator = Some(operator);
+                node.value = Some(value.to_string());
+            }
+            (QueryRestriction::Contains(property, value), _) => {
+                node.field = Some(property.to_string());
+                node.value = Some(value.clone());
+            }
+            (QueryRestriction::Regex(property, regex), _) => {
+                node.field = Some(property.to_string());
+                node.value = Some(regex.to_string());
+            }
+            (QueryRestriction::Has(property, value), _) => {
+                node.field = Some(property.to_string());
+                node.value = Some(value.clone());
+            }
+            (
+                QueryRestriction::Devours(query)
+                | QueryRestriction::DevouredBy(query)
+                | QueryRestriction::Group(query),
+                TermKind::Nested(terms),
+            ) => node.children = self.restrictions(terms, query),
+            (
+                QueryRestriction::Not(query) | QueryRestriction::LenientNot(query),
+                TermKind::Not(term) | TermKind::LenientNot(term),
+            ) => node.children = self.restrictions(std::slice::from_ref(term), query),
+            (
+                QueryRestriction::Or(a, b) | QueryRestriction::Xor(a, b),
+                TermKind::Or(a_term, Some(b_term)) | TermKind::Xor(a_term, Some(b_term)),
+            ) => {
+                node.children = vec![
+                    self.query(std::slice::from_ref(a_term), a, a_term.span),
+                    self.query(std::slice::from_ref(b_term), b, b_term.span),
+                ];
+            }
+            _ => (),
+        }
+        node
+    }
+}
+
+/// Explains a query that `query_parser` accepted, or gives up and returns `None` if counting its nodes runs past `deadline`.
+fn explain_query(
+    original: &str,
+    expanded: String,
+    query: &Query,
+    cards: &HashMap<String, Card>,
+    search_cards: &HashMap<String, Card>,
+    deadline: Instant,
+) -> Option<Explanation> {
+    let explainer = Explainer {
+        cards,
+        search_cards,
+        deadline,
+        expired: Cell::new(false),
+    };
+    let terms = tokenize(&expanded, 0);
+    let tree = explainer.query(
+        &terms,
+        query,
+        Span {
+            start: 0,
+            end: expanded.len(),
+        },
+    );
+    if explainer.expired.get() {
+        return None;
+    }
+
+    Some(Explanation {
+        query_text: query.to_string(),
+        expanded_query: (expanded != original).then_some(expanded),
+        sort: sorting::applied(query, &[query.sort]),
+        tree,
+    })
+}
+
+pub async fn explain(
+    data: web::Data<AppState>,
+    params: web::Query<ExplainParams>,
+) -> impl Responder {
+    let expanded = aliases::expand(&params.query, &*data.aliases.read().await);
+    let expanded = match expanded {
+        Ok(expanded) => expanded,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    let query = match query_parser(&expanded) {
+        Ok(query) => query,
+        Err(error) => {
+            return HttpResponse::BadRequest().body(format!("Query couldn't be parsed: {error:#?}"))
+        }
+    };
+    let cards = Arc::clone(&data.cards);
+    let search_cards = Arc::clone(&data.search_cards);
+    let original = params.into_inner().query;
+    let result = web::block(move || {
+        let explanation = explain_query(
+            &original,
+            expanded,
+            &query,
+            &cards.blocking_read(),
+            &search_cards.blocking_read(),
+            Instant::now() + EXPLAIN_TIME_LIMIT,
+        );
+        explanation.map(|x| serde_json::to_string(&x)).transpose()
+    })
+    .await;
+    match result {
+        Ok(Ok(Some(json))) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json),
+        Ok(Ok(None)) => HttpResponse::BadRequest().body(format!(
+            "The query took longer than {} seconds to explain and was stopped",
+            EXPLAIN_TIME_LIMIT.as_secs()
+        )),
+        Ok(Err(x)) => {
+            HttpResponse::InternalServerError().body(format!("Couldn't explain the query: {x}"))
+        }
+        Err(x) => {
+            HttpResponse::InternalServerError().body(format!("Couldn't explain the query: {x}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use hemoglobin::cards::Card;
+    use hemoglobin::search::query_parser::{get_property_from_name, query_parser, Properties};
+    use hemoglobin::search::{Query, QueryRestriction};
+    use serde_json::json;
+
+    use super::{explain_query, tokenize, Node, Term, TermKind};
+    use crate::normalize;
+
+    /// Every term's text, depth first, read back from its span.
+    fn spans<'a>(query: &'a str, terms: &[Term]) -> Vec<&'a str> {
+        let mut spans = vec![];
+        for term in terms {
+            spans.push(&query[term.span.start..term.span.end]);
+            match &term.kind {
+                TermKind::Nested(inner) => spans.extend(self::spans(query, inner)),
+                TermKind::Not(inner) | TermKind::LenientNot(inner) => {
+                    spans.extend(self::spans(query, std::slice::from_ref(inner.as_ref())));
+                }
+                TermKind::Or(a, b) | TermKind::Xor(a, b) => {
+                    spans.extend(self::spans(query, std::slice::from_ref(a.as_ref())));
+                    if let Some(b) = b {
+                        spans.extend(self::spans(query, std::slice::from_ref(b.as_ref())));
+                    }
+                }
+                TermKind::Word(_) | TermKind::Param(..) | TermKind::Regex | TermKind::Words => (),
+            }
+        }
+        spans
+    }
+
+    /// The structure of a list of terms, written the same way as `parsed_shape` writes the restrictions `query_parser` makes.
+    fn term_shape(terms: &[Term]) -> String {
+        let mut shape = vec![];
+        let mut words = false;
+        for term in terms {
+            shape.push(match &term.kind {
+                TermKind::Word(_) => {
+                    words = true;
+                    continue;
+                }
+                TermKind::Param(name, _) => match get_property_from_name(name) {
+                    Ok(Properties::Sort(_)) => continue,
+                    _ => "param".to_string(),
+                },
+                TermKind::Regex => "regex".to_string(),
+                TermKind::Words => "fuzzy".to_string(),
+                TermKind::Nested(inner) => format!("({})", term_shape(inner)),
+                TermKind::Not(inner) => format!("-{}", term_shape(std::slice::from_ref(inner))),
+                TermKind::LenientNot(inner) => {
+                    format!("--{}", term_shape(std::slice::from_ref(inner)))
+                }
+                TermKind::Or(a, b) => format!(
+                    "[{} | {}]",
+                    term_shape(std::slice::from_ref(a)),
+                    b.as_deref()
+                        .map_or_else(String::new, |b| term_shape(std::slice::from_ref(b)))
+                ),
+                TermKind::Xor(a, b) => format!(
+                    "[{} ^ {}]",
+                    term_shape(std::slice::from_ref(a)),
+                    b.as_deref()
+                        .map_or_else(String::new, |b| term_shape(std::slice::from_ref(b)))
+                ),
+            });
+        }
+        if words {
+            shape.push("fuzzy".to_string());
+        }
+        shape.join(" ")
+    }
+
+    fn parsed_shape(query: &Query) -> String {
+        let shape: Vec<String> = query
+            .restrictions
+            .iter()
+            .map(|restriction| match restriction {
+                QueryRestriction::Fuzzy(_) => "fuzzy".to_string(),
+                QueryRestriction::Comparison(..)
+                | QueryRestriction::Contains(..)
+                | QueryRestriction::Has(..)
+                | QueryRestriction::HasKw(_) => "param".to_string(),
+                QueryRestriction::Regex(..) => "regex".to_string(),
+                QueryRestriction::Devours(query)
+                | QueryRestriction::DevouredBy(query)
+                | QueryRestriction::Group(query) => format!("({})", parsed_shape(query)),
+                QueryRestriction::Not(query) => format!("-{}", parsed_shape(query)),
+                QueryRestriction::LenientNot(query) => format!("--{}", parsed_shape(query)),
+                QueryRestriction::Or(a, b) => {
+                    format!("[{} | {}]", parsed_shape(a), parsed_shape(b))
+                }
+                QueryRestriction::Xor(a, b) => {
+                    format!("[{} ^ {}]", parsed_shape(a), parsed_shape(b))
+                }
+            })
+            .collect();
+        shape.join(" ")
+    }
+
+    #[test]
+    fn spans_point_at_terms() {
+        let query =
+            r#"jäger n:mantis -t:"big bug" (k:insect OR k:undead) dev:(c<2) desc:/bl+/ --ant"#;
+        assert_eq!(
+            spans(query, &tokenize(query, 0)),
+            [
+                "jäger",
+                "n:mantis",
+                r#"-t:"big bug""#,
+                r#"t:"big bug""#,
+                "(k:insect OR k:undead)",
+                "k:insect OR k:undead",
+                "k:insect",
+                "k:undead",
+                "dev:(c<2)",
+                "c<2",
+                "desc:/bl+/",
+                "--ant",
+                "ant",
+            ]
+        );
+    }
+
+    #[test]
+    fn terms_line_up_with_the_parser() {
+        let queries = [
+            "mantis",
+            "vampire mantis c>2 so:cost",
+            r#"n:"vampire mantis" bug"#,
+            "-k:insect --kw:devours",
+            "(c=1 OR c=2) XOR h>3",
+            "dev:(k:insect -c<2) dby:(n:frog)",
+            "desc:/^eats/ t:creature OR t:command",
+            "((a b) OR (c d)) e",
+            "",
+        ];
+        for query in queries {
+            let parsed = query_parser(query).unwrap();
+            assert_eq!(
+                term_shape(&tokenize(query, 0)),
+                parsed_shape(&parsed),
+                "{query}"
+            );
+        }
+    }
+
+    fn cards() -> HashMap<String, Card> {
+        [("mantis", "Vampire Mantis", 2), ("moth", "Moth", 1)]
+            .into_iter()
+            .map(|(id, name, cost)| {
+                let card = json!({
+                    "id": id, "name": name, "description": "", "cost": cost, "health": 1,
+                    "defense": 0, "power": 1, "type": "creature", "kins": ["bug"], "legality": {},
+                });
+                (
+                    id.to_string(),
+                    serde_json::from_str(&card.to_string()).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// Every node's kind and count, depth first.
+    fn counts(node: &Node) -> Vec<(&'static str, Option<usize>)> {
+        let mut all = vec![(node.kind, node.matches)];
+        all.extend(node.children.iter().flat_map(counts));
+        all
+    }
+
+    #[test]
+    fn explaining_queries() {
+        let cards = cards();
+        let search_cards = normalize::fold_cards(&cards);
+        let explain = |query: &str, deadline| {
+            let parsed = query_parser(query).unwrap();
+            explain_query(
+                query,
+                query.to_string(),
+                &parsed,
+                &cards,
+                &search_cards,
+                deadline,
+            )
+        };
+
+        let explanation = explain(
+            "c<2 vampire mantis",
+            Instant::now() + Duration::from_secs(10),
+        );
+        // The words are one fuzzy node, which spans all of them and holds a node for each.
+        assert_eq!(
+            counts(&explanation.unwrap().tree),
+            [
+                ("query", Some(0)),
+                ("comparison", Some(1)),
+                ("fuzzy", Some(1)),
+                ("word", Some(1)),
+                ("word", Some(1)),
+            ]
+        );
+
+        let earlier = Instant::now().checked_sub(Duration::from_secs(1)).unwrap();
+        assert!(explain("c<2 moth", earlier).is_none());
+    }
+}
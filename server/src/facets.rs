@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+
+use hemoglobin::cards::properties::{Number, Read};
+use hemoglobin::cards::Card;
+use hemoglobin::numbers::{MaybeImprecise, MaybeVar};
+use serde::Serialize;
+
+/// How many cards in a set of results have each value of a property.
+#[derive(Serialize, Default)]
+pub struct Facets {
+    types: BTreeMap<String, usize>,
+    kins: BTreeMap<String, usize>,
+    keywords: BTreeMap<String, usize>,
+    sets: BTreeMap<String, usize>,
+    costs: BTreeMap<String, usize>,
+    health: BTreeMap<String, usize>,
+    defense: BTreeMap<String, usize>,
+    power: BTreeMap<String, usize>,
+}
+
+impl Facets {
+    /// Counts a set of results.
+    pub fn count<'a>(cards: impl IntoIterator<Item = &'a Card>) -> Self {
+        let mut facets = Self::default();
+        for card in cards {
+            facets.add(card);
+        }
+        facets
+    }
+
+    fn add(&mut self, card: &Card) {
+        fn count(facet: &mut BTreeMap<String, usize>, value: &str) {
+            *facet.entry(value.to_string()).or_default() += 1;
+        }
+
+        count(&mut self.types, &card.r#type);
+        let mut kins: Vec<&String> = card.kins.iter().collect();
+        kins.sort();
+        kins.dedup();
+        for kin in kins {
+            count(&mut self.kins, kin);
+        }
+        let mut keywords: Vec<&String> = card.keywords.iter().map(|x| &x.name).collect();
+        keywords.sort();
+        keywords.dedup();
+        for keyword in keywords {
+            count(&mut self.keywords, keyword);
+        }
+        if !card.set.is_empty() {
+            count(&mut self.sets, &card.set);
+        }
+
+        if let Some(cost) = card.get_num_property(&Number::Cost) {
+            count(&mut self.costs, &cost.to_string());
+        }
+        // Cards without stats, like commands, aren't counted in stat buckets.
+        for (facet, property) in [
+            (&mut self.health, Number::Health),
+            (&mut self.defense, Number::Defense),
+            (&mut self.power, Number::Power),
+        ] {
+            if let Some(value) = card.get_num_property(&property) {
+                count(facet, &bucket(&value));
+            }
+        }
+    }
+}
+
+/// Groups stat values, so high values that few cards have share a facet.
+fn bucket(value: &MaybeImprecise) -> String {
+    match value {
+        MaybeImprecise::Precise(MaybeVar::Const(value @ 0..=4)) => value.to_string(),
+        MaybeImprecise::Precise(MaybeVar::Const(5 | 6)) => "5-6".to_string(),
+        MaybeImprecise::Precise(MaybeVar::Const(_)) => "7+".to_string(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use hemoglobin::cards::Card;
+    use hemoglobin::numbers::{Comparison, MaybeImprecise, MaybeVar};
+    use serde_json::json;
+
+    use super::{bucket, Facets};
+
+    fn card(r#type: &str, kins: &[&str], keywords: &[&str], set: &str, health: usize) -> Card {
+        let keywords: Vec<_> = keywords.iter().map(|x| json!({"name": x})).collect();
+        let card = json!({
+            "id": "card", "name": "Card", "description": "", "cost": 1, "health": health,
+            "defense": 0, "power": 0, "type": r#type, "kins": kins, "keywords": keywords,
+            "set": set, "legality": {},
+        });
+        serde_json::from_str(&card.to_string()).unwrap()
+    }
+
+    fn counts(facet: &BTreeMap<String, usize>) -> Vec<(&str, usize)> {
+        facet
+            .iter()
+            .map(|(x, count)| (x.as_str(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn counting_cards() {
+        let cards = [
+            // Repeated kins and keywords only count once per card.
+            card(
+                "creature",
+                &["bug", "bug"],
+                &["Flying", "Flying"],
+                "Base",
+                2,
+            ),
+            card("creature", &["bug", "ant"], &[], "", 6),
+            card("command", &[], &["Flying"], "Base", 9),
+        ];
+        let facets = Facets::count(&cards);
+
+        assert_eq!(counts(&facets.types), [("command", 1), ("creature", 2)]);
+        assert_eq!(counts(&facets.kins), [("ant", 1), ("bug", 2)]);
+        assert_eq!(counts(&facets.keywords), [("Flying", 2)]);
+        // Cards without a set aren't counted.
+        assert_eq!(counts(&facets.sets), [("Base", 2)]);
+        assert_eq!(counts(&facets.costs), [("1", 3)]);
+        // Commands have no stats.
+        assert_eq!(counts(&facets.health), [("2", 1), ("5-6", 1)]);
+        assert_eq!(counts(&facets.power), [("0", 2)]);
+
+        assert!(Facets::count(&[]).types.is_empty());
+    }
+
+    #[test]
+    fn buckets() {
+        let precise = |x| bucket(&MaybeImprecise::Precise(MaybeVar::Const(x)));
+        assert_eq!(precise(0), "0");
+        assert_eq!(precise(4), "4");
+        assert_eq!(precise(5), "5-6");
+        assert_eq!(precise(6), "5-6");
+        assert_eq!(precise(7), "7+");
+        assert_eq!(precise(40), "7+");
+        // Variables and ranges keep their own facets.
+        assert_eq!(bucket(&MaybeImprecise::Precise(MaybeVar::Var('X'))), "X");
+        assert_eq!(
+            bucket(&MaybeImprecise::Imprecise(Comparison::GreaterThan(3))),
+            "> 3"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use actix_web::{web, HttpResponse, Responder};
+use hemoglobin::cards::Card;
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, IdViewParam};
+
+/// A play format, as defined in `formats.json`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Format {
+    pub name: String,
+    /// IDs or names of cards that can't be played in this format.
+    #[serde(default)]
+    pub banned: Vec<String>,
+    /// IDs or names of cards that are limited to a single copy in this format.
+    #[serde(default)]
+    pub restricted: Vec<String>,
+    /// Sets whose cards are allowed in this format. If this is empty, every set is allowed.
+    #[serde(default)]
+    pub sets: Vec<String>,
+    /// How many copies of a single card a deck may have. Restricted cards are always limited to one.
+    #[serde(default = "default_copy_limit")]
+    pub copy_limit: usize,
+}
+
+/// The copy limit used when a format doesn't specify one, or when no format is given.
+pub const DEFAULT_COPY_LIMIT: usize = 3;
+
+const fn default_copy_limit() -> usize {
+    DEFAULT_COPY_LIMIT
+}
+
+/// Whether a card can be played in a format.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Legality {
+    Legal,
+    Restricted,
+    Banned,
+    /// The card belongs to a set the format doesn't allow.
+    NotInFormat,
+}
+
+impl Legality {
+    pub const fn is_playable(self) -> bool {
+        matches!(self, Self::Legal | Self::Restricted)
+    }
+}
+
+impl Format {
+    /// How many copies of the card a deck in this format may have.
+    pub fn copy_limit(&self, card: &Card) -> usize {
+        if lists_card(&self.restricted, card) {
+            1
+        } else {
+            self.copy_limit
+        }
+    }
+
+    /// Checks the card against the format's lists, as well as the legality data the card itself carries for this format.
+    pub fn legality(&self, card: &Card) -> Legality {
+        if lists_card(&self.banned, card) {
+            Legality::Banned
+        } else if !self.sets.is_empty() && !self.sets.contains(&card.set) {
+            Legality::NotInFormat
+        } else if card.legality.get(&self.name).is_some_and(|x| x != "y") {
+            Legality::Banned
+        } else if lists_card(&self.restricted, card) {
+            Legality::Restricted
+        } else {
+            Legality::Legal
+        }
+    }
+}
+
+fn lists_card(list: &[String], card: &Card) -> bool {
+    list.iter()
+        .any(|x| *x == card.id || x.eq_ignore_ascii_case(&card.name))
+}
+
+/// Reads a list of formats from a JSON file.
+pub fn load_formats(path: impl AsRef<Path>) -> Result<HashMap<String, Format>, String> {
+    let data = fs::read_to_string(path).map_err(|x| x.to_string())?;
+    let formats: Vec<Format> = serde_json::from_str(&data).map_err(|x| x.to_string())?;
+    Ok(formats.into_iter().map(|x| (x.name.clone(), x)).collect())
+}
+
+#[derive(Serialize)]
+struct LegalityResult<'a> {
+    format: &'a str,
+    id: &'a str,
+    legality: Legality,
+}
+
+pub async fn list_formats(data: web::Data<AppState>) -> impl Responder {
+    let formats = data.formats.read().await;
+    let mut formats: Vec<&Format> = formats.values().collect();
+    formats.sort_by(|a, b| a.name.cmp(&b.name));
+
+    HttpResponse::Ok().json(formats)
+}
+
+pub async fn format_cards(data: web::Data<AppState>, name: web::Path<String>) -> impl Responder {
+    let formats = data.formats.read().await;
+    let Some(format) = formats.get(name.as_str()) else {
+        return HttpResponse::NotFound().body("Not a valid format");
+    };
+
+    let cards = data.cards.read().await;
+    let mut cards: Vec<&Card> = cards
+        .values()
+        .filter(|card| format.legality(card).is_playable())
+        .collect();
+    cards.sort_by(|a, b| a.name.cmp(&b.name));
+
+    HttpResponse::Ok().json(cards)
+}
+
+pub async fn card_legality(
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+    query: web::Query<IdViewParam>,
+) -> impl Responder {
+    let formats = data.formats.read().await;
+    let Some(format) = formats.get(name.as_str()) else {
+        return HttpResponse::NotFound().body("Not a valid format");
+    };
+
+    let cards = data.cards.read().await;
+    cards.get(&query.id).map_or_else(
+        || HttpResponse::BadRequest().body("Not a valid card ID"),
+        |card| {
+            HttpResponse::Ok().json(LegalityResult {
+                format: &format.name,
+                id: &card.id,
+                legality: format.legality(card),
+            })
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use hemoglobin::cards::Card;
+    use serde_json::json;
+
+    use super::{Format, Legality, DEFAULT_COPY_LIMIT};
+
+    fn card(id: &str, name: &str, set: &str, legality: &serde_json::Value) -> Card {
+        serde_json::from_value(json!({
+            "id": id, "name": name, "description": "", "cost": 1, "health": 1, "defense": 0,
+            "power": 1, "type": "creature", "set": set, "legality": legality.clone(),
+        }))
+        .unwrap()
+    }
+
+    fn format() -> Format {
+        serde_json::from_value(json!({
+            "name": "bloodful",
+            "banned": ["banned_bug"],
+            "restricted": ["Rare Bug"],
+            "sets": ["The Forest"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn legality() {
+        let format = format();
+        let legal = card("bug", "Bug", "The Forest", &json!({}));
+        assert_eq!(format.legality(&legal), Legality::Legal);
+        assert_eq!(format.copy_limit(&legal), DEFAULT_COPY_LIMIT);
+
+        // Restricted cards can be listed by name, in any case.
+        let restricted = card("rare_bug", "rare bug", "The Forest", &json!({}));
+        assert_eq!(format.legality(&restricted), Legality::Restricted);
+        assert_eq!(format.copy_limit(&restricted), 1);
+
+        let banned = card("banned_bug", "Banned Bug", "The Forest", &json!({}));
+        assert_eq!(format.legality(&banned), Legality::Banned);
+
+        let other_set = card("sea_bug", "Sea Bug", "The Sea", &json!({}));
+        assert_eq!(format.legality(&other_set), Legality::NotInFormat);
+
+        // The card's own legality data applies to the format with the same name only.
+        let self_banned = card(
+            "old_bug",
+            "Old Bug",
+            "The Forest",
+            &json!({"bloodful": "n"}),
+        );
+        assert_eq!(format.legality(&self_banned), Legality::Banned);
+        let elsewhere = card(
+            "old_bug",
+            "Old Bug",
+            "The Forest",
+            &json!({"bloodless": "n", "bloodful": "y"}),
+        );
+        assert_eq!(format.legality(&elsewhere), Legality::Legal);
+
+        assert!(Legality::Restricted.is_playable());
+        assert!(!Legality::NotInFormat.is_playable());
+    }
+
+    #[test]
+    fn empty_sets_allow_everything() {
+        let format: Format =
+            serde_json::from_value(json!({"name": "casual", "copy_limit": 5})).unwrap();
+        let card = card("sea_bug", "Sea Bug", "The Sea", &json!({}));
+        assert_eq!(format.legality(&card), Legality::Legal);
+        assert_eq!(format.copy_limit(&card), 5);
+    }
+}
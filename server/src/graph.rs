@@ -0,0 +1,384 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use std::sync::Arc;
+
+use actix_web::{web, HttpResponse, Responder};
+use hemoglobin::cards::{Card, KeywordData};
+use hemoglobin::search::query_parser::query_parser;
+use hemoglobin::search::{Query, Sort};
+use serde::{Deserialize, Serialize};
+
+use crate::rich_text::{referenced_ids, searches};
+use crate::{aliases, matching, AppState};
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    #[default]
+    Json,
+    Dot,
+}
+
+#[derive(Deserialize)]
+pub struct GraphParams {
+    /// Only cards matching this query are included, along with the edges between them.
+    query: Option<String>,
+    #[serde(default)]
+    format: GraphFormat,
+    /// Also links cards that share a kin. This adds a lot of edges, so it's off by default.
+    #[serde(default)]
+    kins: bool,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+enum EdgeKind {
+    /// The source's `devours` keyword matches the target.
+    Devours,
+    /// The source's description links to the target.
+    Reference,
+    /// The source's description links to a search that finds the target.
+    Search,
+    /// The cards share a kin. These edges have no direction, and go from the lower ID to the higher one.
+    Kin,
+}
+
+#[derive(Serialize)]
+struct Node<'a> {
+    id: &'a str,
+    name: &'a str,
+    r#type: &'a str,
+}
+
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+struct Edge<'a> {
+    source: &'a str,
+    target: &'a str,
+    kind: EdgeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Graph<'a> {
+    nodes: Vec<Node<'a>>,
+    edges: Vec<Edge<'a>>,
+}
+
+/// The cards in the graph, and their folded copies for searches to match against.
+struct Pool<'a> {
+    cards: &'a HashMap<String, Card>,
+    subset: Vec<&'a Card>,
+    search_subset: Vec<&'a Card>,
+}
+
+impl<'a> Pool<'a> {
+    fn matching(&self, query: &Query) -> Vec<&'a Card> {
+        matching::search(query, &self.search_subset.iter().copied(), self.cards)
+    }
+}
+
+/// Finds every relationship between the cards in `pool`.
+fn edges<'a>(pool: &Pool<'a>, kins: bool) -> Vec<Edge<'a>> {
+    let cards = &pool.subset;
+    let mut edges = BTreeSet::new();
+    for card in cards {
+        let card: &'a Card = card;
+        let mut add = |target: &'a str, kind, label: Option<String>| {
+            if target != card.id {
+                edges.insert(Edge {
+                    source: &card.id,
+                    target,
+                    kind,
+                    label,
+                });
+            }
+        };
+
+        for keyword in card.keywords.iter().filter(|x| x.name == "devours") {
+            if let Some(KeywordData::CardId(devoured)) = &keyword.data {
+                let query = Query {
+                    name: String::new(),
+                    restrictions: devoured.get_as_query(),
+                    sort: Sort::None,
+                };
+                for target in pool.matching(&query) {
+                    add(&target.id, EdgeKind::Devours, None);
+                }
+            }
+        }
+        for id in referenced_ids(&card.description) {
+            // The cards are sorted by ID.
+            if let Ok(idx) = cards.binary_search_by(|x| x.id.as_str().cmp(id)) {
+                add(&cards[idx].id, EdgeKind::Reference, None);
+            }
+        }
+        for search in searches(&card.description) {
+            if let Ok(query) = query_parser(search) {
+                for target in pool.matching(&query) {
+                    add(&target.id, EdgeKind::Search, Some(search.to_string()));
+                }
+            }
+        }
+    }
+
+    if kins {
+        for (idx, a) in cards.iter().enumerate() {
+            for b in &cards[idx + 1..] {
+                let shared: BTreeSet<&str> = a
+                    .kins
+                    .iter()
+                    .filter(|x| b.kins.contains(x))
+                    .map(String::as_str)
+                    .collect();
+                if !shared.is_empty() {
+                    let (source, target) = if a.id < b.id { (a, b) } else { (b, a) };
+                    edges.insert(Edge {
+                        source: &source.id,
+                        target: &target.id,
+                        kind: EdgeKind::Kin,
+                        label: Some(shared.into_iter().collect::<Vec<_>>().join(", ")),
+                    });
+                }
+            }
+        }
+    }
+    edges.into_iter().collect()
+}
+
+fn dot_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn to_dot(graph: &Graph) -> String {
+    let mut dot = String::from("digraph cards {\n");
+    for node in &graph.nodes {
+        let _ = writeln!(
+            dot,
+            "    {} [label={}];",
+            dot_string(node.id),
+            dot_string(node.name)
+        );
+    }
+    for edge in &graph.edges {
+        let label = match (&edge.kind, &edge.label) {
+            (EdgeKind::Devours, _) => "devours".to_string(),
+            (EdgeKind::Reference, _) => "references".to_string(),
+            (EdgeKind::Search, Some(search)) => format!("searches {search}"),
+            (EdgeKind::Kin, Some(kins)) => kins.clone(),
+            (_, None) => String::new(),
+        };
+        let style = match edge.kind {
+            EdgeKind::Kin => ", dir=none, style=dashed",
+            EdgeKind::Search => ", style=dotted",
+            EdgeKind::Devours | EdgeKind::Reference => "",
+        };
+        let _ = writeln!(
+            dot,
+            "    {} -> {} [label={}{style}];",
+            dot_string(edge.source),
+            dot_string(edge.target),
+            dot_string(&label)
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Builds the graph of the cards matching `query`, or of every card if there's no query.
+fn build_graph<'a>(
+    cards: &'a HashMap<String, Card>,
+    search_cards: &'a HashMap<String, Card>,
+    query: Option<&Query>,
+    kins: bool,
+) -> Graph<'a> {
+    let mut subset: Vec<&Card> = query.map_or_else(
+        || cards.values().collect(),
+        |query| matching::search(query, &search_cards.values(), cards),
+    );
+    subset.sort_by(|a, b| a.id.cmp(&b.id));
+    let pool = Pool {
+        cards,
+        search_subset: subset
+            .iter()
+            .filter_map(|x| search_cards.get(&x.id))
+            .collect(),
+        subset,
+    };
+
+    Graph {
+        nodes: pool
+            .subset
+            .iter()
+            .map(|card| Node {
+                id: &card.id,
+                name: &card.name,
+                r#type: &card.r#type,
+            })
+            .collect(),
+        edges: edges(&pool, kins),
+    }
+}
+
+pub async fn card_graph(
+    data: web::Data<AppState>,
+    params: web::Query<GraphParams>,
+) -> impl Responder {
+    let aliases = data.aliases.read().await;
+    let query = match params
+        .query
+        .as_deref()
+        .map(|x| aliases::expand(x, &aliases))
+        .transpose()
+    {
+        Ok(query) => query,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    let query = match query.as_deref().map(query_parser).transpose() {
+        Ok(query) => query,
+        Err(error) => {
+            return HttpResponse::BadRequest().body(format!("Query couldn't be parsed: {error:#?}"))
+        }
+    };
+    drop(aliases);
+
+    // Every card is compared with every other one, so the graph is built off the async workers.
+    let cards = Arc::clone(&data.cards);
+    let search_cards = Arc::clone(&data.search_cards);
+    let (format, kins) = (params.format, params.kins);
+    let result = web::block(move || {
+        let cards = cards.blocking_read();
+        let search_cards = search_cards.blocking_read();
+        let graph = build_graph(&cards, &search_cards, query.as_ref(), kins);
+        match format {
+            GraphFormat::Json => serde_json::to_string(&graph),
+            GraphFormat::Dot => Ok(to_dot(&graph)),
+        }
+    })
+    .await;
+    let content_type = match params.format {
+        GraphFormat::Json => "application/json",
+        GraphFormat::Dot => "text/vnd.graphviz; charset=utf-8",
+    };
+    match result {
+        Ok(Ok(body)) => HttpResponse::Ok().content_type(content_type).body(body),
+        Ok(Err(x)) => {
+            HttpResponse::InternalServerError().body(format!("Couldn't build the graph: {x}"))
+        }
+        Err(x) => {
+            HttpResponse::InternalServerError().body(format!("Couldn't build the graph: {x}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hemoglobin::cards::Card;
+    use hemoglobin::search::query_parser::query_parser;
+    use serde_json::{json, Value};
+
+    use super::{build_graph, dot_string, to_dot, EdgeKind, Graph};
+    use crate::normalize;
+
+    fn card(id: &str, name: &str, kins: &[&str], description: &Value, keywords: &Value) -> Card {
+        // Rich text only deserializes from borrowed strings, so this goes through text.
+        let card = json!({
+            "id": id, "name": name, "description": description, "cost": 1, "health": 1,
+            "defense": 0, "power": 1, "type": "creature", "kins": kins, "keywords": keywords,
+            "legality": {},
+        });
+        serde_json::from_str(&card.to_string()).unwrap()
+    }
+
+    fn cards() -> HashMap<String, Card> {
+        let devours = json!([{"name": "devours", "data": {"type": "CardId", "kins": ["ant"]}}]);
+        [
+            card("mantis", "Mantis", &["insect"], &json!("Eats."), &devours),
+            card(
+                "ant",
+                "Ant",
+                &["insect", "ant"],
+                &json!([
+                    "Calls ",
+                    {"display": "the mantis", "id": "mantis"},
+                    " and ",
+                    {"display": "itself", "id": "ant"},
+                    " and ",
+                    {"display": "moths", "search": "n:moth"},
+                ]),
+                &json!([]),
+            ),
+            card("moth", "The \"Moth\"", &["insect"], &json!(""), &json!([])),
+        ]
+        .into_iter()
+        .map(|x| (x.id.clone(), x))
+        .collect()
+    }
+
+    fn edges<'a>(graph: &'a Graph) -> Vec<(&'a str, &'a str, EdgeKind, Option<&'a str>)> {
+        graph
+            .edges
+            .iter()
+            .map(|x| (x.source, x.target, x.kind, x.label.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn edge_kinds() {
+        let cards = cards();
+        let search_cards = normalize::fold_cards(&cards);
+        let graph = build_graph(&cards, &search_cards, None, false);
+        let ids: Vec<&str> = graph.nodes.iter().map(|x| x.id).collect();
+        assert_eq!(ids, ["ant", "mantis", "moth"]);
+        // Links to a card itself aren't edges.
+        assert_eq!(
+            edges(&graph),
+            [
+                ("ant", "mantis", EdgeKind::Reference, None),
+                ("ant", "moth", EdgeKind::Search, Some("n:moth")),
+                ("mantis", "ant", EdgeKind::Devours, None),
+            ]
+        );
+
+        let graph = build_graph(&cards, &search_cards, None, true);
+        let kins: Vec<_> = edges(&graph)
+            .into_iter()
+            .filter(|x| x.2 == EdgeKind::Kin)
+            .collect();
+        assert_eq!(
+            kins,
+            [
+                ("ant", "mantis", EdgeKind::Kin, Some("insect")),
+                ("ant", "moth", EdgeKind::Kin, Some("insect")),
+                ("mantis", "moth", EdgeKind::Kin, Some("insect")),
+            ]
+        );
+
+        // Only edges between the cards the query finds are kept.
+        let query = query_parser("k:ant OR n:mantis").unwrap();
+        let graph = build_graph(&cards, &search_cards, Some(&query), false);
+        assert_eq!(
+            edges(&graph),
+            [
+                ("ant", "mantis", EdgeKind::Reference, None),
+                ("mantis", "ant", EdgeKind::Devours, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn dot_output() {
+        assert_eq!(dot_string(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+
+        let cards = cards();
+        let search_cards = normalize::fold_cards(&cards);
+        let dot = to_dot(&build_graph(&cards, &search_cards, None, false));
+        assert!(dot.starts_with("digraph cards {\n"));
+        assert!(dot.contains(r#"    "moth" [label="The \"Moth\""];"#));
+        assert!(dot.contains(r#"    "ant" -> "moth" [label="searches n:moth", style=dotted];"#));
+        assert!(dot.contains(r#"    "mantis" -> "ant" [label="devours"];"#));
+        assert!(dot.ends_with("}\n"));
+    }
+}
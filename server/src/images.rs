@@ -0,0 +1,766 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix_files::NamedFile;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{render, AppState};
+
+/// Where card art comes from when it isn't in the cache. Used when `IMAGE_UPSTREAM` isn't set.
+const DEFAULT_UPSTREAM: &str = "https://file.garden/ZJSEzoaUL3bz8vYK/bloodlesscards";
+// Seconds, since `Duration::from_mins` and `Duration::from_hours` need a newer toolchain than the one this builds on.
+const MINUTE: u64 = 60;
+const DAY: u64 = 24 * 60 * MINUTE;
+/// How long a cached image is served before asking the upstream whether it changed.
+const REVALIDATE_AFTER: Duration = Duration::from_secs(DAY);
+/// How long browsers may keep an image before asking again.
+const BROWSER_MAX_AGE: Duration = Duration::from_secs(DAY);
+/// How long an image the upstream doesn't have is remembered as missing, so repeated requests for it don't all reach the upstream.
+const NOT_FOUND_TTL: Duration = Duration::from_secs(5 * MINUTE);
+/// How many missing images are remembered at once. Names come from URLs, so this bounds the memory they can take up.
+const NOT_FOUND_CAPACITY: usize = 10_000;
+/// Upstream responses larger than this aren't downloaded.
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+/// Widths resized images can have. Requested sizes are rounded up to one of these, so each image has a bounded number of variants.
+const VARIANT_WIDTHS: [u32; 3] = [200, 400, 800];
+
+static DOWNLOAD_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub enum Upstream {
+    Http(String),
+    /// Images are read straight from a local directory, without caching.
+    Directory(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    InvalidName,
+    NotFound,
+    Upstream(String),
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Png,
+    Webp,
+}
+
+impl Encoding {
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Webp => "webp",
+        }
+    }
+
+    const fn format(self) -> image::ImageFormat {
+        match self {
+            Self::Png => image::ImageFormat::Png,
+            Self::Webp => image::ImageFormat::WebP,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImageParams {
+    /// Width in pixels. The image is never scaled up.
+    pub size: Option<u32>,
+    pub format: Option<Encoding>,
+}
+
+/// What the cache knows about an image it downloaded, stored next to the image.
+#[derive(Deserialize, Serialize)]
+struct CacheMeta {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched: u64,
+}
+
+pub struct ImageStore {
+    upstream: Upstream,
+    cache_dir: PathBuf,
+    client: reqwest::Client,
+    /// Images the upstream answered 404 for, and when.
+    not_found: Mutex<HashMap<String, Instant>>,
+    /// Images being fetched from the upstream right now. Requests for one of these wait for that fetch instead of starting their own.
+    in_flight: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// The address this server is reached at, used to build absolute image links.
+    public_url: String,
+}
+
+impl ImageStore {
+    /// Reads the upstream from `IMAGE_UPSTREAM` and the cache directory from `IMAGE_CACHE`. Upstreams that aren't HTTP URLs are treated as local directories.
+    /// Absolute image links start with `PUBLIC_URL`, or with the address the server listens on if it isn't set.
+    pub fn from_env() -> Self {
+        let upstream = env::var("IMAGE_UPSTREAM").unwrap_or_else(|_| DEFAULT_UPSTREAM.to_string());
+        let upstream = if upstream.starts_with("http://") || upstream.starts_with("https://") {
+            Upstream::Http(upstream.trim_end_matches('/').to_string())
+        } else {
+            Upstream::Directory(PathBuf::from(upstream))
+        };
+        let cache_dir =
+            PathBuf::from(env::var("IMAGE_CACHE").unwrap_or_else(|_| "./image_cache".to_string()));
+        let public_url = env::var("PUBLIC_URL").unwrap_or_else(|_| {
+            let host = env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+            let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
+            format!("http://{host}:{port}")
+        });
+        Self::new(upstream, cache_dir, &public_url)
+    }
+
+    fn new(upstream: Upstream, cache_dir: PathBuf, public_url: &str) -> Self {
+        if let Err(x) = fs::create_dir_all(&cache_dir) {
+            eprintln!(
+                "Failed to create the image cache at {}: {x}",
+                cache_dir.display()
+            );
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Unable to create the image client");
+
+        Self {
+            upstream,
+            cache_dir,
+            client,
+            not_found: Mutex::new(HashMap::new()),
+            in_flight: Mutex::new(HashMap::new()),
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// An absolute link to an image served by this server, for places like embeds and exports where relative links don't work.
+    pub fn public_link(&self, name: &str) -> String {
+        format!("{}/api/image/{name}", self.public_url)
+    }
+
+    /// Returns the path to a local copy of the image, downloading or revalidating it first if needed.
+    /// If the upstream can't be reached, a stale copy is returned instead.
+    pub async fn local_path(&self, name: &str) -> Result<PathBuf, ImageError> {
+        if !is_valid_name(name) {
+            return Err(ImageError::InvalidName);
+        }
+
+        match &self.upstream {
+            Upstream::Directory(dir) => {
+                let path = dir.join(name);
+                if path.is_file() {
+                    Ok(path)
+                } else {
+                    Err(ImageError::NotFound)
+                }
+            }
+            Upstream::Http(base) => {
+                let lock = Arc::clone(
+                    self.in_flight
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .entry(name.to_string())
+                        .or_default(),
+                );
+                // Whoever waited for another fetch finds its result in the cache or in `not_found`.
+                let result = {
+                    let _fetching = lock.lock().await;
+                    self.fetch(base, name).await
+                };
+                let mut in_flight = self
+                    .in_flight
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                // Only `in_flight` and this fetch still hold the lock, so nobody else is waiting on it.
+                if Arc::strong_count(&lock) == 2 {
+                    in_flight.remove(name);
+                }
+                result
+            }
+        }
+    }
+
+    /// Like `local_path`, but a cached image is returned as is, without asking the upstream whether it changed. Only images that aren't cached at all are downloaded.
+    pub async fn cached_path(&self, name: &str) -> Result<PathBuf, ImageError> {
+        if matches!(self.upstream, Upstream::Http(_)) && is_valid_name(name) {
+            let path = self.cache_dir.join(name);
+            if path.is_file() {
+                return Ok(path);
+            }
+        }
+        self.local_path(name).await
+    }
+
+    /// Returns the path to a resized or re-encoded copy of a local image, generating it if it's missing or older than the original.
+    pub fn variant(
+        &self,
+        source: &Path,
+        name: &str,
+        width: Option<u32>,
+        encoding: Encoding,
+    ) -> Result<PathBuf, image::ImageError> {
+        let width = width.map(variant_width);
+        let size = width.map_or_else(|| "full".to_string(), |x| x.to_string());
+        let dir = self.cache_dir.join("variants");
+        let path = dir.join(format!("{name}-{size}.{}", encoding.extension()));
+
+        let source_modified = fs::metadata(source)?.modified()?;
+        if fs::metadata(&path)
+            .and_then(|x| x.modified())
+            .is_ok_and(|modified| modified >= source_modified)
+        {
+            return Ok(path);
+        }
+
+        let mut image = image::open(source)?;
+        if let Some(width) = width.filter(|x| *x < image.width()) {
+            image = image.resize(width, u32::MAX, image::imageops::FilterType::Triangle);
+        }
+        if encoding == Encoding::Webp {
+            // The WebP encoder only takes 8-bit RGB(A) images.
+            image = image::DynamicImage::ImageRgba8(image.into_rgba8());
+        }
+
+        let mut bytes = io::Cursor::new(vec![]);
+        image.write_to(&mut bytes, encoding.format())?;
+        fs::create_dir_all(&dir)?;
+        write_atomically(&path, bytes.get_ref())?;
+        Ok(path)
+    }
+
+    /// Returns the path to a rasterized copy of a card's rendered frame. Each card has one copy per size and encoding, which is replaced when the card's SVG changes.
+    pub fn rendered(
+        &self,
+        id: &str,
+        svg: &str,
+        width: Option<u32>,
+        encoding: Encoding,
+    ) -> Result<PathBuf, String> {
+        if !is_valid_name(id) {
+            return Err(format!("{id} can't be used as a file name"));
+        }
+        let width = width.map_or(render::CARD_WIDTH, variant_width);
+        let mut hasher = DefaultHasher::new();
+        svg.hash(&mut hasher);
+        let hash = format!("{:016x}", hasher.finish());
+        let dir = self.cache_dir.join("rendered");
+        let path = dir.join(format!("{id}-{width}.{}", encoding.extension()));
+        // The hash of the SVG the copy was rasterized from. It's written after the image, so a copy is never taken for a newer SVG than the one it shows.
+        let hash_path = dir.join(format!("{id}-{width}.{}.hash", encoding.extension()));
+        if path.is_file() && fs::read_to_string(&hash_path).is_ok_and(|x| x == hash) {
+            return Ok(path);
+        }
+
+        let image = render::rasterize(svg, width)?;
+        let mut bytes = io::Cursor::new(vec![]);
+        image
+            .write_to(&mut bytes, encoding.format())
+            .map_err(|x| x.to_string())?;
+        fs::create_dir_all(&dir).map_err(|x| x.to_string())?;
+        write_atomically(&path, bytes.get_ref()).map_err(|x| x.to_string())?;
+        write_atomically(&hash_path, hash.as_bytes()).map_err(|x| x.to_string())?;
+        Ok(path)
+    }
+
+    async fn fetch(&self, base: &str, name: &str) -> Result<PathBuf, ImageError> {
+        let path = self.cache_dir.join(name);
+        let meta_path = self.cache_dir.join(format!("{name}.meta"));
+        let meta = {
+            let (path, meta_path) = (path.clone(), meta_path.clone());
+            web::block(move || {
+                fs::read_to_string(&meta_path)
+                    .ok()
+                    .and_then(|x| serde_json::from_str::<CacheMeta>(&x).ok())
+                    .filter(|_| path.is_file())
+            })
+            .await
+            .ok()
+            .flatten()
+        };
+        let now = unix_now();
+
+        if meta
+            .as_ref()
+            .is_some_and(|meta| now.saturating_sub(meta.fetched) < REVALIDATE_AFTER.as_secs())
+        {
+            return Ok(path);
+        }
+
+        if self.recently_missing(name) {
+            return Err(ImageError::NotFound);
+        }
+
+        let mut request = self.client.get(format!("{base}/{name}"));
+        if let Some(meta) = &meta {
+            if let Some(etag) = &meta.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &meta.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let stale = |error: String| {
+            if meta.is_some() {
+                eprintln!("Serving a stale copy of {name}: {error}");
+                Ok(path.clone())
+            } else {
+                Err(ImageError::Upstream(error))
+            }
+        };
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(x) => return stale(x.to_string()),
+        };
+
+        match response.status() {
+            reqwest::StatusCode::NOT_MODIFIED if meta.is_some() => {
+                let meta = CacheMeta {
+                    fetched: now,
+                    ..meta.unwrap()
+                };
+                write_meta(meta_path, meta).await;
+                Ok(path)
+            }
+            reqwest::StatusCode::NOT_FOUND => {
+                self.remember_missing(name);
+                Err(ImageError::NotFound)
+            }
+            status if status.is_success() => {
+                let header = |name| {
+                    response
+                        .headers()
+                        .get(name)
+                        .and_then(|x| x.to_str().ok())
+                        .map(ToString::to_string)
+                };
+                let meta = CacheMeta {
+                    etag: header(reqwest::header::ETAG),
+                    last_modified: header(reqwest::header::LAST_MODIFIED),
+                    fetched: now,
+                };
+                let bytes = match read_limited(response, MAX_IMAGE_BYTES).await {
+                    Ok(bytes) => bytes,
+                    Err(x) => return stale(x),
+                };
+                let written = {
+                    let path = path.clone();
+                    web::block(move || write_atomically(&path, &bytes)).await
+                };
+                match written {
+                    Ok(Ok(())) => (),
+                    Ok(Err(x)) => return stale(format!("Couldn't write to the cache: {x}")),
+                    Err(x) => return stale(format!("Couldn't write to the cache: {x}")),
+                }
+                write_meta(meta_path, meta).await;
+                Ok(path)
+            }
+            status => stale(format!("Upstream responded with {status}")),
+        }
+    }
+
+    fn recently_missing(&self, name: &str) -> bool {
+        let not_found = self
+            .not_found
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        not_found
+            .get(name)
+            .is_some_and(|since| since.elapsed() < NOT_FOUND_TTL)
+    }
+
+    fn remember_missing(&self, name: &str) {
+        let mut not_found = self
+            .not_found
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if not_found.len() >= NOT_FOUND_CAPACITY {
+            not_found.retain(|_, since| since.elapsed() < NOT_FOUND_TTL);
+            if not_found.len() >= NOT_FOUND_CAPACITY {
+                not_found.clear();
+            }
+        }
+        not_found.insert(name.to_string(), Instant::now());
+    }
+}
+
+/// Reads a response body, failing as soon as it's longer than `limit` bytes.
+async fn read_limited(mut response: reqwest::Response, limit: usize) -> Result<Vec<u8>, String> {
+    let too_large = || format!("Upstream image is larger than {limit} bytes");
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut bytes = vec![];
+    while let Some(chunk) = response.chunk().await.map_err(|x| x.to_string())? {
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Rounds a requested width up to one of `VARIANT_WIDTHS`.
+fn variant_width(width: u32) -> u32 {
+    VARIANT_WIDTHS
+        .into_iter()
+        .find(|x| *x >= width)
+        .unwrap_or(VARIANT_WIDTHS[VARIANT_WIDTHS.len() - 1])
+}
+
+/// Image names come from URLs, so they must not be able to leave the image directory.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !Path::new(name)
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("meta"))
+        && !name
+            .chars()
+            .any(|x| x == '/' || x == '\\' || x.is_control())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs())
+}
+
+/// Writes to a temporary file first so concurrent readers never see a partial file.
+pub fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let counter = DOWNLOAD_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp = path.with_extension(format!("{counter}.part"));
+    fs::write(&temp, bytes)?;
+    fs::rename(&temp, path)
+}
+
+async fn write_meta(path: PathBuf, meta: CacheMeta) {
+    let result = web::block(move || {
+        let result = serde_json::to_string(&meta)
+            .map_err(io::Error::other)
+            .and_then(|x| write_atomically(&path, x.as_bytes()));
+        if let Err(x) = result {
+            eprintln!("Failed to write {}: {x}", path.display());
+        }
+    })
+    .await;
+    if let Err(x) = result {
+        eprintln!("Failed to write an image's cache metadata: {x}");
+    }
+}
+
+/// Serves a file with caching headers, answering conditional requests with 304 Not Modified. The file is streamed by actix-files, off the async workers.
+pub async fn file_response(req: &HttpRequest, path: &Path) -> io::Result<HttpResponse> {
+    let mut response = NamedFile::open_async(path).await?.into_response(req);
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&format!("public, max-age={}", BROWSER_MAX_AGE.as_secs()))
+            .map_err(io::Error::other)?,
+    );
+    Ok(response)
+}
+
+pub async fn serve_image(
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+    params: web::Query<ImageParams>,
+    req: HttpRequest,
+) -> io::Result<HttpResponse> {
+    match data.images.local_path(&name).await {
+        Ok(path) if params.size.is_none() && params.format.is_none() => {
+            file_response(&req, &path).await
+        }
+        Ok(path) => {
+            let encoding = params.format.unwrap_or(Encoding::Png);
+            let size = params.size;
+            let data = data.clone();
+            let variant = web::block(move || data.images.variant(&path, &name, size, encoding))
+                .await
+                .map_err(io::Error::other)?;
+            match variant {
+                Ok(path) => file_response(&req, &path).await,
+                Err(x) => Ok(HttpResponse::InternalServerError()
+                    .body(format!("Couldn't resize the image: {x}"))),
+            }
+        }
+        Err(ImageError::InvalidName) => {
+            Ok(HttpResponse::BadRequest().body("Not a valid image name"))
+        }
+        Err(ImageError::NotFound) => {
+            // Cards without finished art get a rendered frame instead of a broken image.
+            let card = render::card_for_image(&*data.cards.read().await, &name)
+                .map(|card| (card.id.clone(), render::card_svg(card)));
+            match card {
+                Some((id, svg)) => {
+                    let encoding = params.format.unwrap_or(Encoding::Png);
+                    rendered_response(data, &req, id, svg, params.size, encoding).await
+                }
+                None => Ok(HttpResponse::NotFound().body("Image not found")),
+            }
+        }
+        Err(ImageError::Upstream(x)) => {
+            Ok(HttpResponse::BadGateway().body(format!("Couldn't fetch the image: {x}")))
+        }
+    }
+}
+
+/// Serves a rasterized rendered card.
+pub async fn rendered_response(
+    data: web::Data<AppState>,
+    req: &HttpRequest,
+    id: String,
+    svg: String,
+    size: Option<u32>,
+    encoding: Encoding,
+) -> io::Result<HttpResponse> {
+    let path = web::block(move || data.images.rendered(&id, &svg, size, encoding))
+        .await
+        .map_err(io::Error::other)?;
+    match path {
+        Ok(path) => file_response(req, &path).await,
+        Err(x) => {
+            Ok(HttpResponse::InternalServerError().body(format!("Couldn't render the card: {x}")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    use actix_web::http::header;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use futures_util::future::join;
+
+    use super::{
+        is_valid_name, variant_width, CacheMeta, Encoding, ImageError, ImageStore, Upstream,
+        NOT_FOUND_TTL,
+    };
+
+    /// Every request the test upstream got, as the image's name and its `If-None-Match` header.
+    type Requests = web::Data<Mutex<Vec<(String, Option<String>)>>>;
+
+    async fn upstream(
+        requests: Requests,
+        name: web::Path<String>,
+        req: HttpRequest,
+    ) -> HttpResponse {
+        let etag = req
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .and_then(|x| x.to_str().ok())
+            .map(ToString::to_string);
+        requests.lock().unwrap().push((name.clone(), etag.clone()));
+        match name.as_str() {
+            "Missing.png" => HttpResponse::NotFound().finish(),
+            _ if etag.as_deref() == Some("\"v1\"") => HttpResponse::NotModified().finish(),
+            name => {
+                if name == "Slow.png" {
+                    actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+                }
+                HttpResponse::Ok()
+                    .insert_header((header::ETAG, "\"v1\""))
+                    .body("image")
+            }
+        }
+    }
+
+    /// Starts an upstream on a free port, and an image store that downloads from it into an empty cache.
+    fn store(test: &str) -> (ImageStore, Requests, PathBuf) {
+        let requests = Requests::default();
+        let data = requests.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route("/{name}", web::get().to(upstream))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let cache = std::env::temp_dir().join(format!("hemolymph-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&cache);
+        let store = ImageStore::new(
+            Upstream::Http(format!("http://{address}")),
+            cache.clone(),
+            "http://localhost",
+        );
+        (store, requests, cache)
+    }
+
+    fn hits(requests: &Requests, name: &str) -> Vec<Option<String>> {
+        let requests = requests.lock().unwrap();
+        requests
+            .iter()
+            .filter(|(x, _)| x == name)
+            .map(|(_, etag)| etag.clone())
+            .collect()
+    }
+
+    #[test]
+    fn valid_names() {
+        assert!(is_valid_name("VampireMantis.png"));
+        assert!(is_valid_name("Jäger.png"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(".hidden"));
+        assert!(!is_valid_name("../cards.json"));
+        assert!(!is_valid_name("dir/Moth.png"));
+        assert!(!is_valid_name("dir\\Moth.png"));
+        assert!(!is_valid_name("Moth\n.png"));
+        // Cache metadata lives next to the images, and can't be requested as one.
+        assert!(!is_valid_name("Moth.png.meta"));
+        assert!(!is_valid_name("Moth.png.META"));
+    }
+
+    #[test]
+    fn variant_widths() {
+        assert_eq!(variant_width(0), 200);
+        assert_eq!(variant_width(200), 200);
+        assert_eq!(variant_width(201), 400);
+        assert_eq!(variant_width(800), 800);
+        assert_eq!(variant_width(5000), 800);
+    }
+
+    #[actix_web::test]
+    async fn variants() {
+        let cache = std::env::temp_dir().join(format!("hemolymph-variants-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&cache);
+        let store = ImageStore::new(Upstream::Directory(cache.clone()), cache.clone(), "");
+        let source = cache.join("Wide.png");
+        image::RgbImage::new(600, 10).save(&source).unwrap();
+
+        let resized = store
+            .variant(&source, "Wide.png", Some(300), Encoding::Webp)
+            .unwrap();
+        assert_eq!(resized.file_name().unwrap(), "Wide.png-400.webp");
+        assert_eq!(image::open(&resized).unwrap().width(), 400);
+        // Images are never scaled up.
+        let full = store
+            .variant(&source, "Wide.png", Some(700), Encoding::Png)
+            .unwrap();
+        assert_eq!(full.file_name().unwrap(), "Wide.png-800.png");
+        assert_eq!(image::open(&full).unwrap().width(), 600);
+
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn rendered_cards_are_replaced() {
+        let cache = std::env::temp_dir().join(format!("hemolymph-rendered-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&cache);
+        let store = ImageStore::new(Upstream::Directory(cache.clone()), cache.clone(), "");
+        let svg = |color: &str| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="10" height="10" fill="{color}"/></svg>"#
+            )
+        };
+
+        let path = store
+            .rendered("moth", &svg("red"), Some(200), Encoding::Png)
+            .unwrap();
+        assert_eq!(path.file_name().unwrap(), "moth-200.png");
+        let red = fs::read(&path).unwrap();
+        assert_eq!(
+            store.rendered("moth", &svg("red"), Some(150), Encoding::Png),
+            Ok(path.clone())
+        );
+        assert_eq!(fs::read(&path).unwrap(), red);
+
+        // An edited card overwrites its old copy instead of leaving it behind.
+        assert_eq!(
+            store.rendered("moth", &svg("blue"), Some(200), Encoding::Png),
+            Ok(path.clone())
+        );
+        assert_ne!(fs::read(&path).unwrap(), red);
+        assert_eq!(fs::read_dir(cache.join("rendered")).unwrap().count(), 2);
+
+        assert!(store
+            .rendered("../moth", &svg("red"), None, Encoding::Png)
+            .is_err());
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn revalidation() {
+        let (store, requests, cache) = store("revalidation");
+        let path = store.local_path("Moth.png").await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "image");
+        // Fresh copies are served without asking the upstream.
+        store.local_path("Moth.png").await.unwrap();
+        assert_eq!(hits(&requests, "Moth.png"), [None]);
+
+        let meta_path = cache.join("Moth.png.meta");
+        let meta: CacheMeta =
+            serde_json::from_str(&fs::read_to_string(&meta_path).unwrap()).unwrap();
+        assert_eq!(meta.etag.as_deref(), Some("\"v1\""));
+        let stale = CacheMeta { fetched: 0, ..meta };
+        fs::write(&meta_path, serde_json::to_string(&stale).unwrap()).unwrap();
+        // Proxy sheets take stale copies as they are.
+        assert_eq!(store.cached_path("Moth.png").await.unwrap(), path);
+        assert_eq!(hits(&requests, "Moth.png"), [None]);
+
+        // Stale copies are revalidated with their ETag, and a 304 keeps them for another day.
+        assert_eq!(store.local_path("Moth.png").await.unwrap(), path);
+        assert_eq!(
+            hits(&requests, "Moth.png"),
+            [None, Some("\"v1\"".to_string())]
+        );
+        let meta: CacheMeta =
+            serde_json::from_str(&fs::read_to_string(&meta_path).unwrap()).unwrap();
+        assert!(meta.fetched > 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "image");
+
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn missing_images_are_remembered() {
+        let (store, requests, cache) = store("missing");
+        for _ in 0..3 {
+            assert!(matches!(
+                store.local_path("Missing.png").await,
+                Err(ImageError::NotFound)
+            ));
+        }
+        assert_eq!(hits(&requests, "Missing.png").len(), 1);
+
+        let expired = Instant::now().checked_sub(NOT_FOUND_TTL).unwrap();
+        store
+            .not_found
+            .lock()
+            .unwrap()
+            .insert("Missing.png".to_string(), expired);
+        assert!(store.local_path("Missing.png").await.is_err());
+        assert_eq!(hits(&requests, "Missing.png").len(), 2);
+
+        let _ = fs::remove_dir_all(&cache);
+    }
+
+    #[actix_web::test]
+    async fn concurrent_fetches_are_shared() {
+        let (store, requests, cache) = store("concurrent");
+        let (a, b) = join(store.local_path("Slow.png"), store.local_path("Slow.png")).await;
+        assert_eq!(a.unwrap(), b.unwrap());
+        assert_eq!(hits(&requests, "Slow.png").len(), 1);
+        assert!(store.in_flight.lock().unwrap().is_empty());
+
+        fs::remove_dir_all(&cache).unwrap();
+    }
+}
@@ -3,14 +3,45 @@
 #![allow(clippy::significant_drop_tightening)]
 #![allow(clippy::future_not_send)]
 
+mod aliases;
+mod alternatives;
+mod backlinks;
+mod cards;
+mod deck;
+mod explain;
+mod facets;
+mod formats;
+mod graph;
+mod images;
+mod matching;
+mod normalize;
+mod projection;
+mod ranking;
+mod render;
+mod rich_text;
+mod search;
+mod similar;
+mod sorting;
+mod suggest;
+mod tabular;
+
 use actix_cors::Cors;
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use alternatives::Alternative;
+use backlinks::Backlinks;
+use facets::Facets;
+use formats::Format;
 use hemoglobin::cards::Card;
 use hemoglobin::search::query_parser::query_parser;
+use hemoglobin::search::Sort;
 use hemolymph_frontend::ServerAppProps;
+use images::ImageStore;
 use notify::RecursiveMode;
 use notify_debouncer_mini::new_debouncer;
+use projection::Projected;
+use ranking::Relevance;
 use serde::{Deserialize, Serialize};
+use sorting::AppliedSort;
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
@@ -18,6 +49,8 @@ use std::sync::mpsc::TryRecvError;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{env, fs, io};
+use suggest::Vocabulary;
+use tabular::TableFormat;
 use tokio::sync::RwLock;
 use tokio::time::sleep;
 use yew::ServerRenderer;
@@ -25,10 +58,37 @@ use yew::ServerRenderer;
 #[derive(Deserialize)]
 struct QueryParams {
     query: Option<String>,
+    /// Returns the results as a table instead of JSON.
+    format: Option<TableFormat>,
+    /// Comma separated columns for tables.
+    columns: Option<String>,
+    /// Comma separated properties to sort by, overriding the query's own sort. Later properties break ties.
+    sort: Option<String>,
+    /// `asc` or `desc`, for each property in `sort`.
+    order: Option<String>,
+    /// Comma separated card fields to include in the results. Every field is included if this is missing.
+    fields: Option<String>,
+    /// Counts how many results have each type, kin, keyword, set, cost and stat.
+    #[serde(default)]
+    facets: bool,
 }
 
 struct AppState {
     cards: Arc<RwLock<HashMap<String, Card>>>,
+    /// Copies of the cards with the text searches look at folded, rebuilt whenever the cards are. Searches match against these, so accents and case don't matter.
+    search_cards: Arc<RwLock<HashMap<String, Card>>>,
+    formats: Arc<RwLock<HashMap<String, Format>>>,
+    /// Queries that can be used in other queries as `@name`.
+    aliases: Arc<RwLock<HashMap<String, String>>>,
+    images: ImageStore,
+    /// Values `/api/suggest` completes, rebuilt whenever the cards are.
+    vocabulary: Arc<RwLock<Vocabulary>>,
+    /// Cards that link to each card, rebuilt whenever the cards are.
+    backlinks: Arc<RwLock<Backlinks>>,
+    /// Word statistics fuzzy searches are ranked with, rebuilt whenever the cards are.
+    ranking: Arc<RwLock<ranking::Index>>,
+    /// Cards in the format used before hemoglobin's, searched with the older query engine while datasets are migrated.
+    legacy_cards: Arc<RwLock<Vec<cards::Card>>>,
 }
 
 #[derive(Serialize)]
@@ -36,7 +96,16 @@ struct AppState {
 pub enum QueryResult<'a> {
     CardList {
         query_text: String,
-        content: Vec<&'a Card>,
+        content: Vec<Projected<'a>>,
+        sort: Vec<AppliedSort>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        facets: Option<Box<Facets>>,
+        /// How relevant each card in `content` is to the query's words, in the same order. Empty if the query has no words.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        relevance: Vec<Relevance>,
+        /// Close queries that do find cards, when this one found none.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        alternatives: Vec<Alternative>,
     },
     Error {
         message: String,
@@ -48,6 +117,8 @@ struct IdViewParam {
     id: String,
 }
 
+// index.html uses `{placeholder}` markers that are filled with `str::replace`.
+#[allow(clippy::literal_string_with_formatting_args)]
 async fn serve_index(data: web::Data<AppState>, req: HttpRequest) -> io::Result<HttpResponse> {
     let cards = data.cards.read().await;
     let path = req.path().to_string();
@@ -58,12 +129,12 @@ async fn serve_index(data: web::Data<AppState>, req: HttpRequest) -> io::Result<
         .map(|x| x.to_str().unwrap())
         .and_then(|x| cards.get(x).cloned());
 
-    if path.extension().map_or(false, |x| x == "js") {
+    if path.extension().is_some_and(|x| x == "js") {
         let content = fs::read_to_string(format!("dist/{}", path.to_string_lossy()))?;
         Ok(HttpResponse::Ok()
             .content_type("application/javascript; charset=utf-8")
             .body(content))
-    } else if path.extension().map_or(false, |x| x == "wasm") {
+    } else if path.extension().is_some_and(|x| x == "wasm") {
         let content = fs::read(format!("dist/{}", path.to_string_lossy()))?;
         Ok(HttpResponse::Ok()
             .content_type("application/wasm")
@@ -75,7 +146,8 @@ async fn serve_index(data: web::Data<AppState>, req: HttpRequest) -> io::Result<
             let (description, name) = match card_details {
                 Some(card) => (
                     card.description.to_string(),
-                    get_filegarden_link(&card.get_image_path(0)),
+                    data.images
+                        .public_link(&image_file_name(&card.get_image_path(0))),
                 ),
                 None => (
                     "A search engine for Bloodless cards.".to_string(),
@@ -111,26 +183,57 @@ async fn serve_index(data: web::Data<AppState>, req: HttpRequest) -> io::Result<
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let environment = env::var("RUST_ENV").unwrap_or_else(|_| "development".to_string());
+    let env_file = match environment.as_str() {
+        "production" => ".env.production",
+        _ => ".env",
+    };
+    dotenv::from_filename(env_file).ok();
+
     let data = fs::read_to_string("./static/cards.json").expect("Unable to read file");
     let cards: Vec<Card> = serde_json::from_str(&data).expect("Unable to parse JSON");
     let cards = create_card_map(cards);
+    let vocabulary = Vocabulary::new(&cards);
+    let backlinks = Backlinks::new(&cards);
+    let search_cards = normalize::fold_cards(&cards);
+    let ranking = ranking::Index::new(&cards);
+
+    let formats = formats::load_formats("./static/formats.json").unwrap_or_else(|x| {
+        eprintln!("Failed to load formats.json, starting without formats: {x}");
+        HashMap::new()
+    });
+
+    let aliases = aliases::load_aliases(aliases::ALIASES_PATH).unwrap_or_else(|x| {
+        eprintln!("Failed to load aliases.json, starting without aliases: {x}");
+        HashMap::new()
+    });
+
+    let legacy_cards = search::load_legacy_cards(search::LEGACY_CARDS_PATH).unwrap_or_default();
 
     let app_state = web::Data::new(AppState {
         cards: Arc::new(RwLock::new(cards)),
+        search_cards: Arc::new(RwLock::new(search_cards)),
+        formats: Arc::new(RwLock::new(formats)),
+        aliases: Arc::new(RwLock::new(aliases)),
+        images: ImageStore::from_env(),
+        vocabulary: Arc::new(RwLock::new(vocabulary)),
+        backlinks: Arc::new(RwLock::new(backlinks)),
+        ranking: Arc::new(RwLock::new(ranking)),
+        legacy_cards: Arc::new(RwLock::new(legacy_cards)),
     });
 
-    let environment = env::var("RUST_ENV").unwrap_or_else(|_| "development".to_string());
-    let env_file = match environment.as_str() {
-        "production" => ".env.production",
-        _ => ".env",
-    };
-    dotenv::from_filename(env_file).ok();
-
     // Read the HOST and PORT variables
     let host = env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
 
     let cards_pointer = Arc::clone(&app_state.cards);
+    let search_cards_pointer = Arc::clone(&app_state.search_cards);
+    let formats_pointer = Arc::clone(&app_state.formats);
+    let aliases_pointer = Arc::clone(&app_state.aliases);
+    let vocabulary_pointer = Arc::clone(&app_state.vocabulary);
+    let backlinks_pointer = Arc::clone(&app_state.backlinks);
+    let ranking_pointer = Arc::clone(&app_state.ranking);
+    let legacy_cards_pointer = Arc::clone(&app_state.legacy_cards);
 
     tokio::spawn(async move {
         let (tx, rx) = std::sync::mpsc::channel();
@@ -150,10 +253,40 @@ async fn main() -> std::io::Result<()> {
                                 Ok(data) => {
                                     let mut cards = cards_pointer.write().await;
                                     *cards = create_card_map(data);
+                                    *vocabulary_pointer.write().await = Vocabulary::new(&cards);
+                                    *backlinks_pointer.write().await = Backlinks::new(&cards);
+                                    *search_cards_pointer.write().await =
+                                        normalize::fold_cards(&cards);
+                                    *ranking_pointer.write().await = ranking::Index::new(&cards);
                                     println!("Successfully reloaded cards.json");
                                 }
                                 Err(x) => eprintln!("Failed to load cards.json: {x:#?}"),
                             }
+                        } else if event.path.ends_with("formats.json") {
+                            match formats::load_formats("./static/formats.json") {
+                                Ok(data) => {
+                                    let mut formats = formats_pointer.write().await;
+                                    *formats = data;
+                                    println!("Successfully reloaded formats.json");
+                                }
+                                Err(x) => eprintln!("Failed to load formats.json: {x}"),
+                            }
+                        } else if event.path.ends_with("aliases.json") {
+                            match aliases::load_aliases(aliases::ALIASES_PATH) {
+                                Ok(data) => {
+                                    *aliases_pointer.write().await = data;
+                                    println!("Successfully reloaded aliases.json");
+                                }
+                                Err(x) => eprintln!("Failed to load aliases.json: {x}"),
+                            }
+                        } else if event.path.ends_with("legacy_cards.json") {
+                            match search::load_legacy_cards(search::LEGACY_CARDS_PATH) {
+                                Ok(data) => {
+                                    *legacy_cards_pointer.write().await = data;
+                                    println!("Successfully reloaded legacy_cards.json");
+                                }
+                                Err(x) => eprintln!("Failed to load legacy_cards.json: {x}"),
+                            }
                         }
                     }
                 }
@@ -173,7 +306,50 @@ async fn main() -> std::io::Result<()> {
             .wrap(cors)
             .app_data(app_state.clone())
             .route("/api/search", web::get().to(search))
+            .route("/api/legacy/search", web::get().to(search::legacy_search))
             .route("/api/card", web::get().to(view_card))
+            .route("/api/card/{id}/render", web::get().to(render::render_card))
+            .route(
+                "/api/card/{id}/similar",
+                web::get().to(similar::similar_cards),
+            )
+            .route(
+                "/api/card/{id}/backlinks",
+                web::get().to(backlinks::backlinks),
+            )
+            .route("/api/image/{name}", web::get().to(images::serve_image))
+            .route("/api/suggest", web::get().to(suggest::suggest))
+            .route("/api/explain", web::get().to(explain::explain))
+            .route("/api/graph", web::get().to(graph::card_graph))
+            .route("/api/aliases", web::get().to(aliases::list_aliases))
+            .route("/api/aliases/{name}", web::put().to(aliases::set_alias))
+            .route(
+                "/api/aliases/{name}",
+                web::delete().to(aliases::delete_alias),
+            )
+            .route("/api/deck/validate", web::post().to(deck::validate))
+            .route("/api/deck/stats", web::post().to(deck::stats::deck_stats))
+            .route(
+                "/api/deck/export/marrow",
+                web::post().to(deck::export::marrow),
+            )
+            .route(
+                "/api/deck/export/tts",
+                web::post().to(deck::export::tabletop),
+            )
+            .route(
+                "/api/deck/proxies",
+                web::post().to(deck::proxies::proxy_sheet),
+            )
+            .route("/api/formats", web::get().to(formats::list_formats))
+            .route(
+                "/api/formats/{name}/cards",
+                web::get().to(formats::format_cards),
+            )
+            .route(
+                "/api/formats/{name}/legality",
+                web::get().to(formats::card_legality),
+            )
             .default_service(web::route().to(serve_index))
     })
     .bind(format!("{host}:{port}"))?
@@ -186,26 +362,101 @@ fn create_card_map(vec: Vec<Card>) -> HashMap<String, Card> {
 }
 
 async fn search(data: web::Data<AppState>, query: web::Query<QueryParams>) -> impl Responder {
-    let cards = data.cards.read().await;
-    let cards = cards.values();
+    let card_map = data.cards.read().await;
+    let search_cards = data.search_cards.read().await;
+    let query_text = query.query.clone().unwrap_or_default();
+    // Tables can't hold an error, so they get it as plain text with an error status.
+    let error = |message: String| {
+        if query.format.is_some() {
+            HttpResponse::BadRequest().body(message)
+        } else {
+            HttpResponse::Ok().json(QueryResult::Error { message })
+        }
+    };
+    let aliases = data.aliases.read().await;
+    let expanded = match aliases::expand(&query_text, &aliases) {
+        Ok(expanded) => expanded,
+        Err(message) => return error(message),
+    };
 
-    match query_parser(&query.query.clone().unwrap_or_default()) {
-        Ok(query_restrictions) => {
-            let results = hemoglobin::search::search(&query_restrictions, cards);
+    match query_parser(&expanded) {
+        Ok(mut query_restrictions) => {
+            let sorts = match (&query.sort, &query.order) {
+                (Some(sort), order) => sorting::parse_sort(sort, order.as_deref()),
+                (None, Some(order)) => sorting::parse_single_order(order)
+                    .and_then(|order| sorting::with_order(query_restrictions.sort, order))
+                    .map(|sort| vec![sort]),
+                (None, None) => Ok(vec![query_restrictions.sort]),
+            };
+            let sorts = match sorts {
+                Ok(sorts) => sorts,
+                Err(message) => return error(message),
+            };
 
+            let mut results =
+                matching::search(&query_restrictions, &search_cards.values(), &card_map);
+            let facets = query.facets.then(|| Facets::count(results.iter().copied()));
+            let mut relevance = ranking::rank(
+                &*data.ranking.read().await,
+                &results,
+                &query_restrictions.name,
+            );
+            sorting::sort_cards(&mut results, &sorts, &relevance);
+            // Only used to describe the query.
+            query_restrictions.sort = sorts.first().copied().unwrap_or(Sort::None);
+
+            if let Some(format) = query.format {
+                let columns = match tabular::parse_columns(query.columns.as_deref()) {
+                    Ok(columns) => columns,
+                    Err(message) => return error(message),
+                };
+                let ids: Vec<String> = results.iter().map(|x| x.id.clone()).collect();
+                return tabular::respond(data.clone(), format, columns, &ids);
+            }
+
+            let fields = match query.fields.as_deref().map(projection::parse_fields) {
+                Some(Ok(fields)) => Some(fields),
+                Some(Err(message)) => {
+                    return HttpResponse::Ok().json(QueryResult::Error { message })
+                }
+                None => None,
+            };
+            if results.is_empty() && !query_text.trim().is_empty() {
+                let description = format!("{query_restrictions}");
+                let sort = sorting::applied(&query_restrictions, &sorts);
+                // Alternatives are searched for on another thread, which reads the cards again. Holding on to these until it's done would block it behind any reload waiting for them.
+                drop((card_map, search_cards, aliases));
+                return HttpResponse::Ok().json(QueryResult::CardList {
+                    query_text: description,
+                    content: vec![],
+                    sort,
+                    facets: facets.map(Box::new),
+                    relevance: vec![],
+                    alternatives: alternatives::find(&data, query_text).await,
+                });
+            }
+            let relevance = results
+                .iter()
+                .filter_map(|card| relevance.remove(card.id.as_str()))
+                .collect();
             let results = QueryResult::CardList {
-                content: results,
+                content: results
+                    .into_iter()
+                    .map(|card| Projected {
+                        card,
+                        fields: fields.as_deref(),
+                    })
+                    .collect(),
                 query_text: format!("{query_restrictions}"),
+                sort: sorting::applied(&query_restrictions, &sorts),
+                facets: facets.map(Box::new),
+                relevance,
+                alternatives: vec![],
             };
 
             HttpResponse::Ok().json(results)
         }
-        Err(error) => {
-            let error = QueryResult::Error {
-                message: format!("Query couldn't be parsed: {error:#?}"),
-            };
-            HttpResponse::Ok().json(error)
-        }
+        Err(x) => error(format!("Query couldn't be parsed: {x:#?}")),
     }
 }
 
@@ -220,9 +471,7 @@ async fn view_card(data: web::Data<AppState>, query: web::Query<IdViewParam>) ->
     )
 }
 
-fn get_filegarden_link(name: &str) -> String {
-    format!(
-        "https://file.garden/ZJSEzoaUL3bz8vYK/bloodlesscards/{}.png",
-        name.replace(' ', "").replace('ä', "a")
-    )
+/// The name of the file an image is stored as, both upstream and in the image cache.
+fn image_file_name(name: &str) -> String {
+    format!("{}.png", name.replace(' ', "").replace('ä', "a"))
 }
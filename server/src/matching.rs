@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use hemoglobin::cards::Card;
+use hemoglobin::search::{matches_query, Cache, Query, Ternary};
+
+use crate::normalize;
+
+/// Filters cards the same way `hemoglobin::search::search` does, but leaves them unsorted.
+/// The query is folded with `normalize::fold_query` and matched against `search_cards`, the folded copies of the cards, but the original cards from `cards` are returned.
+pub fn search<'a, 'b, I>(
+    query: &Query,
+    search_cards: &I,
+    cards: &'a HashMap<String, Card>,
+) -> Vec<&'a Card>
+where
+    I: IntoIterator<Item = &'b Card> + Clone,
+{
+    // Searches without a deadline always finish.
+    filter(query, search_cards, cards, None).unwrap_or_default()
+}
+
+/// Like `search`, but gives up and returns `None` if it's still searching at `deadline`. The deadline is checked between cards, so a single card's `devours` subqueries can still run past it.
+pub fn search_until<'a, 'b, I>(
+    query: &Query,
+    search_cards: &I,
+    cards: &'a HashMap<String, Card>,
+    deadline: Instant,
+) -> Option<Vec<&'a Card>>
+where
+    I: IntoIterator<Item = &'b Card> + Clone,
+{
+    filter(query, search_cards, cards, Some(deadline))
+}
+
+fn filter<'a, 'b, I>(
+    query: &Query,
+    search_cards: &I,
+    cards: &'a HashMap<String, Card>,
+    deadline: Option<Instant>,
+) -> Option<Vec<&'a Card>>
+where
+    I: IntoIterator<Item = &'b Card> + Clone,
+{
+    let mut query = query.clone();
+    normalize::fold_query(&mut query);
+    let cache = Cache::new(HashMap::new());
+    let mut results = vec![];
+    for card in search_cards.clone() {
+        if deadline.is_some_and(|x| Instant::now() > x) {
+            return None;
+        }
+        if matches_query(card, &query, search_cards, &cache) == Ternary::True {
+            results.extend(cards.get(&card.id));
+        }
+    }
+    Some(results)
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use hemoglobin::cards::properties::{Array, Text};
+use hemoglobin::cards::rich_text::RichString;
+use hemoglobin::cards::{Card, CardId, Keyword, KeywordData};
+use hemoglobin::search::{Query, QueryRestriction};
+use regex::Regex;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Folds text so it can be compared without caring about case, accents or compatibility characters: it's decomposed with NFKD, its diacritics are dropped and it's lowercased. "Jäger" and "JAGER" both fold to "jager".
+pub fn fold(text: &str) -> String {
+    text.nfkd()
+        .filter(|x| !is_combining_mark(*x))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn fold_rich_string(string: &RichString) -> RichString {
+    let mut folded = RichString::default();
+    folded.push_string(fold(&string.to_string()));
+    folded
+}
+
+fn fold_keyword(keyword: &Keyword) -> Keyword {
+    Keyword {
+        name: fold(&keyword.name),
+        data: match &keyword.data {
+            Some(KeywordData::CardId(id)) => Some(KeywordData::CardId(fold_card_id(id))),
+            data => data.clone(),
+        },
+    }
+}
+
+/// `devours` keywords describe cards with a `CardId`, which has to be folded too so it still matches the folded cards.
+fn fold_card_id(id: &CardId) -> CardId {
+    CardId {
+        name: id.name.as_deref().map(fold),
+        description: id.description.as_ref().map(fold_rich_string),
+        keywords: id
+            .keywords
+            .as_ref()
+            .map(|x| x.iter().map(fold_keyword).collect()),
+        r#type: id.r#type.as_deref().map(fold),
+        kins: id
+            .kins
+            .as_ref()
+            .map(|x| x.iter().map(|x| fold(x)).collect()),
+        ..id.clone()
+    }
+}
+
+/// A copy of a card with the text searches look at folded. Only used for matching, never shown.
+fn fold_card(card: &Card) -> Card {
+    Card {
+        name: fold(&card.name),
+        description: fold_rich_string(&card.description),
+        r#type: fold(&card.r#type),
+        kins: card.kins.iter().map(|x| fold(x)).collect(),
+        keywords: card.keywords.iter().map(fold_keyword).collect(),
+        ..card.clone()
+    }
+}
+
+/// Folds every card, keeping their IDs, so search results can be mapped back to the originals.
+pub fn fold_cards(cards: &HashMap<String, Card>) -> HashMap<String, Card> {
+    cards
+        .iter()
+        .map(|(id, card)| (id.clone(), fold_card(card)))
+        .collect()
+}
+
+/// Folds the values of a query that are matched against folded text, so they match regardless of accents.
+pub fn fold_query(query: &mut Query) {
+    query.name = fold(&query.name);
+    for restriction in &mut query.restrictions {
+        match restriction {
+            QueryRestriction::Fuzzy(value)
+            | QueryRestriction::Contains(Text::Name | Text::Type | Text::Description, value)
+            | QueryRestriction::Has(Array::Kins, value)
+            | QueryRestriction::HasKw(value) => *value = fold(value),
+            QueryRestriction::Regex(Text::Name | Text::Type | Text::Description, regex) => {
+                if let Ok(folded) = Regex::new(&fold(regex.as_str())) {
+                    *regex = folded;
+                }
+            }
+            QueryRestriction::Devours(query)
+            | QueryRestriction::DevouredBy(query)
+            | QueryRestriction::Not(query)
+            | QueryRestriction::LenientNot(query)
+            | QueryRestriction::Group(query) => fold_query(query),
+            QueryRestriction::Or(a, b) | QueryRestriction::Xor(a, b) => {
+                fold_query(a);
+                fold_query(b);
+            }
+            QueryRestriction::Contains(..)
+            | QueryRestriction::Regex(..)
+            | QueryRestriction::Has(..)
+            | QueryRestriction::Comparison(..) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hemoglobin::search::query_parser::query_parser;
+    use hemoglobin::search::QueryRestriction;
+
+    use super::{fold, fold_query};
+
+    #[test]
+    fn folding() {
+        assert_eq!(fold("Jäger"), "jager");
+        assert_eq!(fold("JAGER"), "jager");
+        assert_eq!(fold("Ñandú Ömer"), "nandu omer");
+        // Compatibility characters are spelled out.
+        assert_eq!(fold("ﬁre Ⅳ"), "fire iv");
+        assert_eq!(fold("İstanbul"), "istanbul");
+        assert_eq!(fold(""), "");
+        assert_eq!(fold("3 + 4"), "3 + 4");
+    }
+
+    #[test]
+    fn folding_queries() {
+        let mut query = query_parser("Jäger n:Ärt (k:Ïnsect or kw:Ñ)").unwrap();
+        fold_query(&mut query);
+        assert_eq!(query.name, "jager");
+
+        let mut values = vec![];
+        let mut pending: Vec<&QueryRestriction> = query.restrictions.iter().collect();
+        while let Some(restriction) = pending.pop() {
+            match restriction {
+                QueryRestriction::Contains(_, value)
+                | QueryRestriction::Has(_, value)
+                | QueryRestriction::HasKw(value) => values.push(value.as_str()),
+                QueryRestriction::Group(query) => pending.extend(&query.restrictions),
+                QueryRestriction::Or(a, b) => {
+                    pending.extend(&a.restrictions);
+                    pending.extend(&b.restrictions);
+                }
+                _ => (),
+            }
+        }
+        values.sort_unstable();
+        assert_eq!(values, ["art", "insect", "n"]);
+    }
+}
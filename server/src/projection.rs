@@ -0,0 +1,161 @@
+use hemoglobin::cards::Card;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+/// A card field that can be requested with the `fields` search parameter. Names match the card's JSON keys.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Id,
+    Name,
+    Images,
+    /// The path of the card's first image. This isn't a stored field, but it's all the search grid needs to show a card.
+    Image,
+    Description,
+    Cost,
+    Health,
+    Defense,
+    Power,
+    Type,
+    Keywords,
+    Kins,
+    Abilities,
+    Set,
+    Legality,
+    Other,
+    Functions,
+    FlavorText,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "id" => Self::Id,
+            "name" => Self::Name,
+            "images" => Self::Images,
+            "image" => Self::Image,
+            "description" => Self::Description,
+            "cost" => Self::Cost,
+            "health" => Self::Health,
+            "defense" => Self::Defense,
+            "power" => Self::Power,
+            "type" => Self::Type,
+            "keywords" => Self::Keywords,
+            "kins" => Self::Kins,
+            "abilities" => Self::Abilities,
+            "set" => Self::Set,
+            "legality" => Self::Legality,
+            "other" => Self::Other,
+            "functions" => Self::Functions,
+            "flavor_text" => Self::FlavorText,
+            _ => return None,
+        })
+    }
+}
+
+/// Parses a comma separated list of fields. Repeated fields are only included once, and a list without any fields is an error, since it would only give empty cards.
+pub fn parse_fields(fields: &str) -> Result<Vec<Field>, String> {
+    let mut parsed = vec![];
+    for name in fields.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+        let field = Field::from_name(name).ok_or_else(|| format!("Unknown field: {name}"))?;
+        if !parsed.contains(&field) {
+            parsed.push(field);
+        }
+    }
+    if parsed.is_empty() {
+        return Err("No fields were given".to_string());
+    }
+    Ok(parsed)
+}
+
+/// A card that only serializes some of its fields. Every field is serialized if there are no fields given.
+pub struct Projected<'a> {
+    pub card: &'a Card,
+    pub fields: Option<&'a [Field]>,
+}
+
+impl Serialize for Projected<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(fields) = self.fields else {
+            return self.card.serialize(serializer);
+        };
+
+        let card = self.card;
+        let mut map = serializer.serialize_map(Some(fields.len()))?;
+        for field in fields {
+            match field {
+                Field::Id => map.serialize_entry("id", &card.id)?,
+                Field::Name => map.serialize_entry("name", &card.name)?,
+                Field::Images => map.serialize_entry("images", &card.images)?,
+                Field::Image => map.serialize_entry("image", &card.get_image_path(0))?,
+                Field::Description => map.serialize_entry("description", &card.description)?,
+                Field::Cost => map.serialize_entry("cost", &card.cost)?,
+                Field::Health => map.serialize_entry("health", &card.health)?,
+                Field::Defense => map.serialize_entry("defense", &card.defense)?,
+                Field::Power => map.serialize_entry("power", &card.power)?,
+                Field::Type => map.serialize_entry("type", &card.r#type)?,
+                Field::Keywords => map.serialize_entry("keywords", &card.keywords)?,
+                Field::Kins => map.serialize_entry("kins", &card.kins)?,
+                Field::Abilities => map.serialize_entry("abilities", &card.abilities)?,
+                Field::Set => map.serialize_entry("set", &card.set)?,
+                Field::Legality => map.serialize_entry("legality", &card.legality)?,
+                Field::Other => map.serialize_entry("other", &card.other)?,
+                Field::Functions => map.serialize_entry("functions", &card.functions)?,
+                Field::FlavorText => map.serialize_entry("flavor_text", &card.flavor_text)?,
+            }
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hemoglobin::cards::Card;
+    use serde_json::{json, Value};
+
+    use super::{parse_fields, Field, Projected};
+
+    fn mantis() -> Card {
+        let card = json!({
+            "id": "vampire_mantis", "name": "Vampire Mantis", "description": "Drinks.", "cost": 2,
+            "health": 1, "defense": 0, "power": 3, "type": "creature", "kins": ["bug"],
+            "legality": {},
+        });
+        serde_json::from_str(&card.to_string()).unwrap()
+    }
+
+    fn project(card: &Card, fields: Option<&[Field]>) -> Value {
+        serde_json::to_value(Projected { card, fields }).unwrap()
+    }
+
+    #[test]
+    fn parsing_fields() {
+        assert!(parse_fields(" name , cost,,name ") == Ok(vec![Field::Name, Field::Cost]));
+        assert!(parse_fields("flavor_text") == Ok(vec![Field::FlavorText]));
+        assert_eq!(
+            parse_fields("name,smell").map(|_| ()),
+            Err("Unknown field: smell".to_string())
+        );
+        assert!(parse_fields("").is_err());
+        assert!(parse_fields(" , ").is_err());
+    }
+
+    #[test]
+    fn projecting_cards() {
+        let card = mantis();
+        let fields = parse_fields("kins,id,image").unwrap();
+        let projected = project(&card, Some(&fields));
+        assert_eq!(
+            projected,
+            json!({"kins": ["bug"], "id": "vampire_mantis", "image": card.get_image_path(0)})
+        );
+        // Keys come out in the order they were asked for.
+        let text = serde_json::to_string(&Projected {
+            card: &card,
+            fields: Some(&fields),
+        })
+        .unwrap();
+        assert!(text.starts_with(r#"{"kins":["bug"],"id":"vampire_mantis","image":"#));
+
+        assert_eq!(project(&card, None), serde_json::to_value(&card).unwrap());
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use hemoglobin::cards::Card;
+use serde::Serialize;
+
+use crate::normalize;
+
+/// BM25 parameters. `K1` limits how much repeating a term adds to a score, and `B` how much longer fields are penalized.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// A field fuzzy terms are scored against.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Name,
+    Kins,
+    Keywords,
+    Description,
+}
+
+impl Field {
+    const ALL: [Self; 4] = [Self::Name, Self::Kins, Self::Keywords, Self::Description];
+
+    /// How much a match in this field is worth compared to one in the description.
+    const fn weight(self) -> f64 {
+        match self {
+            Self::Name => 3.0,
+            Self::Kins | Self::Keywords => 2.0,
+            Self::Description => 1.0,
+        }
+    }
+
+    /// The text of the field. Kins and keywords have one value per kin or keyword.
+    fn values(self, card: &Card) -> Vec<String> {
+        match self {
+            Self::Name => vec![card.name.clone()],
+            Self::Kins => card.kins.clone(),
+            Self::Keywords => card.keywords.iter().map(|x| x.name.clone()).collect(),
+            Self::Description => vec![card.description.to_string()],
+        }
+    }
+}
+
+/// Splits text into words folded with `normalize::fold`, along with where each word starts.
+fn tokens(text: &str) -> impl Iterator<Item = (usize, String)> + '_ {
+    text.split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(move |token| {
+            let start = token.as_ptr() as usize - text.as_ptr() as usize;
+            (start, normalize::fold(token))
+        })
+}
+
+#[derive(Default)]
+struct FieldStats {
+    /// The cards that have each word in this field, by their position in the index.
+    postings: BTreeMap<String, Vec<usize>>,
+    average_length: f64,
+}
+
+/// Word statistics for every card, rebuilt from the card map every time it's loaded. They're needed to tell rare words, which say a lot about a card, from common ones.
+#[derive(Default)]
+pub struct Index {
+    cards: usize,
+    fields: HashMap<Field, FieldStats>,
+}
+
+impl Index {
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(cards: &HashMap<String, Card>) -> Self {
+        let fields = Field::ALL
+            .into_iter()
+            .map(|field| {
+                let mut stats = FieldStats::default();
+                let mut length = 0_usize;
+                for (idx, card) in cards.values().enumerate() {
+                    for value in field.values(card) {
+                        for (_, token) in tokens(&value) {
+                            length += 1;
+                            let cards = stats.postings.entry(token).or_default();
+                            if cards.last() != Some(&idx) {
+                                cards.push(idx);
+                            }
+                        }
+                    }
+                }
+                stats.average_length = length as f64 / cards.len().max(1) as f64;
+                (field, stats)
+            })
+            .collect();
+        Self {
+            cards: cards.len(),
+            fields,
+        }
+    }
+
+    /// How rare a term is in a field. Terms match every word they're the start of.
+    #[allow(clippy::cast_precision_loss)]
+    fn idf(&self, field: Field, term: &str) -> f64 {
+        let with_term: HashSet<usize> = self.fields[&field]
+            .postings
+            .range(term.to_string()..)
+            .take_while(|(token, _)| token.starts_with(term))
+            .flat_map(|(_, cards)| cards.iter().copied())
+            .collect();
+        let frequency = with_term.len() as f64;
+        ((self.cards as f64 - frequency + 0.5) / (frequency + 0.5)).ln_1p()
+    }
+}
+
+/// Where terms matched in one value of a field, as byte offsets into `text`.
+#[derive(Serialize)]
+pub struct FieldMatch {
+    field: Field,
+    text: String,
+    spans: Vec<[usize; 2]>,
+}
+
+/// How relevant a search result is to the fuzzy part of a query, and why.
+#[derive(Serialize)]
+pub struct Relevance {
+    pub score: f64,
+    matches: Vec<FieldMatch>,
+}
+
+/// Where a word that starts with `term` ends up matching it. Folding can change how long text is, so characters are folded one by one until they're as long as `term`.
+fn prefix_end(text: &str, start: usize, term: &str) -> usize {
+    let length = term.chars().count();
+    let mut folded = 0;
+    for (idx, ch) in text[start..].char_indices() {
+        if folded >= length {
+            return start + idx;
+        }
+        folded += normalize::fold(ch.encode_utf8(&mut [0; 4])).chars().count();
+    }
+    text.len()
+}
+
+/// Scores `card` with BM25 over every field, adding up each field's score times its weight.
+#[allow(clippy::cast_precision_loss, clippy::suboptimal_flops)]
+fn score(index: &Index, card: &Card, terms: &[String]) -> Relevance {
+    let mut score = 0.0;
+    let mut matches = vec![];
+    for field in Field::ALL {
+        let values = field.values(card);
+        let length = values.iter().map(|x| tokens(x).count()).sum::<usize>() as f64;
+        let average_length = index.fields[&field].average_length.max(1.0);
+        let mut frequencies = vec![0_usize; terms.len()];
+
+        for text in values {
+            let mut spans = vec![];
+            for (start, token) in tokens(&text) {
+                for (idx, term) in terms.iter().enumerate() {
+                    if token.starts_with(term.as_str()) {
+                        frequencies[idx] += 1;
+                        spans.push([start, prefix_end(&text, start, term)]);
+                    }
+                }
+            }
+            if !spans.is_empty() {
+                spans.sort_unstable();
+                spans.dedup();
+                matches.push(FieldMatch { field, text, spans });
+            }
+        }
+
+        for (term, frequency) in terms.iter().zip(frequencies) {
+            if frequency > 0 {
+                let frequency = frequency as f64;
+                let saturation = frequency * (K1 + 1.0)
+                    / (frequency + K1 * (1.0 - B + B * length / average_length));
+                score += field.weight() * index.idf(field, term) * saturation;
+            }
+        }
+    }
+    Relevance { score, matches }
+}
+
+/// Scores every card against the words of a fuzzy search. Nothing is scored if there are no words.
+pub fn rank<'a>(index: &Index, cards: &[&'a Card], name: &str) -> HashMap<&'a str, Relevance> {
+    let mut terms: Vec<String> = tokens(name).map(|(_, token)| token).collect();
+    terms.sort();
+    terms.dedup();
+    if terms.is_empty() {
+        return HashMap::new();
+    }
+    cards
+        .iter()
+        .map(|card| (card.id.as_str(), score(index, card, &terms)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prefix_end, tokens};
+
+    #[test]
+    fn splitting_terms() {
+        let text = "Jäger-Ant, the SWARM!";
+        let split: Vec<(usize, String)> = tokens(text).collect();
+        assert_eq!(
+            split,
+            [
+                (0, "jager".to_string()),
+                (7, "ant".to_string()),
+                (12, "the".to_string()),
+                (16, "swarm".to_string())
+            ]
+        );
+        for (start, _) in &split {
+            assert!(text.is_char_boundary(*start));
+        }
+        assert_eq!(tokens(" -- ").count(), 0);
+    }
+
+    #[test]
+    fn prefix_spans() {
+        // "ä" is two bytes but folds to a single character.
+        assert_eq!(prefix_end("Jäger", 0, "jag"), 4);
+        assert_eq!(prefix_end("Jäger", 0, "jager"), 6);
+        assert_eq!(prefix_end("The Ant", 4, "an"), 6);
+        // Compatibility characters can fold to more than one character.
+        assert_eq!(prefix_end("ﬁre", 0, "fi"), 3);
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::{Arc, OnceLock};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use hemoglobin::cards::rich_text::{RichElement, RichString};
+use hemoglobin::cards::{Card, ImageSource};
+use htmlize::escape_text;
+use resvg::usvg::fontdb;
+use resvg::{tiny_skia, usvg};
+
+use crate::images::{self, Encoding, ImageParams};
+use crate::{image_file_name, AppState};
+
+/// Size of a rendered card, matching the size of finished card art.
+pub const CARD_WIDTH: u32 = 750;
+const CARD_HEIGHT: u32 = 1050;
+const TEXT_LEFT: u32 = 72;
+const TEXT_WIDTH: u32 = 606;
+const TEXT_TOP: u32 = 668;
+const TEXT_HEIGHT: u32 = 268;
+/// Average width of a character relative to the font size, used to wrap lines.
+const CHARACTER_WIDTH: f32 = 0.53;
+const LINE_HEIGHT: f32 = 1.25;
+/// Font sizes the card text is tried at, from largest to smallest, until it fits.
+const TEXT_SIZES: [u32; 6] = [30, 28, 26, 24, 22, 20];
+
+enum ParagraphKind {
+    Text,
+    /// A step of a saga, with its number.
+    Step(usize),
+    Flavor,
+}
+
+struct Paragraph {
+    kind: ParagraphKind,
+    text: String,
+}
+
+/// Splits rich text into the paragraphs the card text box shows, the same way the details page does.
+fn paragraphs(text: &RichString) -> Vec<Paragraph> {
+    fn flush(paragraphs: &mut Vec<Paragraph>, current: &mut String) {
+        let text = current.trim();
+        if !text.is_empty() {
+            paragraphs.push(Paragraph {
+                kind: ParagraphKind::Text,
+                text: text.to_string(),
+            });
+        }
+        current.clear();
+    }
+
+    let mut paragraphs = vec![];
+    let mut current = String::new();
+    for element in text {
+        match element {
+            RichElement::String(string) => {
+                let mut lines = string.split('\n');
+                if let Some(first) = lines.next() {
+                    current.push_str(first);
+                }
+                for line in lines {
+                    flush(&mut paragraphs, &mut current);
+                    current.push_str(line);
+                }
+            }
+            RichElement::CardId { display, .. }
+            | RichElement::SpecificCard { display, .. }
+            | RichElement::CardSearch { display, .. } => current.push_str(display),
+            RichElement::Saga(steps) => {
+                flush(&mut paragraphs, &mut current);
+                for (idx, step) in steps.iter().enumerate() {
+                    paragraphs.push(Paragraph {
+                        kind: ParagraphKind::Step(idx + 1),
+                        text: step
+                            .to_string()
+                            .split_whitespace()
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    });
+                }
+            }
+            RichElement::LineBreak => flush(&mut paragraphs, &mut current),
+        }
+    }
+    flush(&mut paragraphs, &mut current);
+    paragraphs
+}
+
+/// Greedily wraps text into lines of at most `width` characters. Words longer than a line get a line of their own.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+/// Lays out the text box at the largest font size it fits at. Returns the font size and the lines, with their indentation in characters.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::suboptimal_flops
+)]
+fn layout_text(paragraphs: &[Paragraph]) -> (u32, Vec<(&Paragraph, usize, String)>) {
+    let mut layout = (0, vec![]);
+    for size in TEXT_SIZES {
+        let width = (TEXT_WIDTH as f32 / (size as f32 * CHARACTER_WIDTH)) as usize;
+        let mut lines = vec![];
+        for paragraph in paragraphs {
+            let indent = match paragraph.kind {
+                ParagraphKind::Step(_) => 3,
+                ParagraphKind::Text | ParagraphKind::Flavor => 0,
+            };
+            for line in wrap(&paragraph.text, width - indent) {
+                lines.push((paragraph, indent, line));
+            }
+        }
+        let height = lines.len() as f32 * size as f32 * LINE_HEIGHT
+            + paragraphs.len().saturating_sub(1) as f32 * size as f32 * 0.4;
+        layout = (size, lines);
+        if height <= TEXT_HEIGHT as f32 {
+            break;
+        }
+    }
+    layout
+}
+
+/// Draws a card frame filled in with the card's data, for cards that don't have finished art yet.
+#[allow(clippy::cast_precision_loss, clippy::suboptimal_flops)]
+pub fn card_svg(card: &Card) -> String {
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{CARD_WIDTH}" height="{CARD_HEIGHT}" viewBox="0 0 {CARD_WIDTH} {CARD_HEIGHT}" font-family="DejaVu Sans, Verdana, sans-serif">"#
+    );
+    svg.push_str(r##"<rect width="750" height="1050" rx="36" fill="#1c1a19"/>"##);
+    svg.push_str(r##"<rect x="36" y="36" width="678" height="978" rx="18" fill="#efe6d2"/>"##);
+
+    let _ = write!(
+        svg,
+        r##"<rect x="52" y="52" width="646" height="80" rx="10" fill="#8c1c13"/><text x="72" y="106" font-size="40" font-weight="bold" fill="#fff"{}>{}</text>"##,
+        squeeze(&card.name, 40.0, 606.0),
+        escape_text(&card.name)
+    );
+
+    svg.push_str(r##"<rect x="64" y="148" width="622" height="420" rx="6" fill="#c9bca0"/>"##);
+    svg.push_str(r##"<text x="375" y="368" font-size="30" font-style="italic" fill="#7a6e58" text-anchor="middle">No art yet</text>"##);
+
+    let mut type_line = title_case(&card.r#type);
+    if !card.r#type.contains("blood flask") {
+        let _ = write!(type_line, " :: {} Blood", card.cost);
+    }
+    let _ = write!(
+        svg,
+        r##"<rect x="52" y="584" width="646" height="64" rx="10" fill="#d8cbb0"/><text x="72" y="627" font-size="30" fill="#1c1a19"{}>{}</text>"##,
+        squeeze(&type_line, 30.0, 606.0),
+        escape_text(&type_line)
+    );
+
+    let mut paragraphs = paragraphs(&card.description);
+    paragraphs.extend(
+        card.flavor_text
+            .lines()
+            .filter(|x| !x.trim().is_empty())
+            .map(|x| Paragraph {
+                kind: ParagraphKind::Flavor,
+                text: x.trim().to_string(),
+            }),
+    );
+    let (size, lines) = layout_text(&paragraphs);
+    let size = size as f32;
+    let mut y = TEXT_TOP as f32 + size;
+    let mut previous: Option<&Paragraph> = None;
+    for (paragraph, indent, line) in lines {
+        if let Some(previous) = previous {
+            y += size * LINE_HEIGHT;
+            if !std::ptr::eq(previous, paragraph) {
+                y += size * 0.4;
+            }
+        }
+        let first_line = previous.is_none_or(|x| !std::ptr::eq(x, paragraph));
+        previous = Some(paragraph);
+
+        let x = TEXT_LEFT as f32 + indent as f32 * size * CHARACTER_WIDTH;
+        let style = match paragraph.kind {
+            ParagraphKind::Flavor => r##" font-style="italic" fill="#5a5044""##,
+            ParagraphKind::Text | ParagraphKind::Step(_) => r##" fill="#1c1a19""##,
+        };
+        if let (ParagraphKind::Step(step), true) = (&paragraph.kind, first_line) {
+            let _ = write!(
+                svg,
+                r#"<text x="{TEXT_LEFT}" y="{y}" font-size="{size}" font-weight="bold"{style}>{step}.</text>"#
+            );
+        }
+        let _ = write!(
+            svg,
+            r#"<text x="{x}" y="{y}" font-size="{size}"{style}>{}</text>"#,
+            escape_text(&line)
+        );
+    }
+
+    if !card.r#type.contains("command") {
+        let _ = write!(
+            svg,
+            r##"<rect x="52" y="950" width="646" height="48" rx="10" fill="#8c1c13"/><text x="375" y="986" font-size="32" font-weight="bold" fill="#fff" text-anchor="middle">{}/{}/{}</text>"##,
+            card.health, card.defense, card.power
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Attributes that squeeze text that would overflow its box. Shorter text is left alone.
+#[allow(clippy::cast_precision_loss)]
+fn squeeze(text: &str, size: f32, max: f32) -> String {
+    if text.chars().count() as f32 * size * CHARACTER_WIDTH > max {
+        format!(r#" textLength="{max}" lengthAdjust="spacingAndGlyphs""#)
+    } else {
+        String::new()
+    }
+}
+
+fn title_case(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map_or_else(String::new, |first| {
+                first.to_uppercase().chain(chars).collect()
+            })
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Finds the card an image file belongs to, so it can be rendered when the file doesn't exist.
+pub fn card_for_image<'a>(cards: &'a HashMap<String, Card>, name: &str) -> Option<&'a Card> {
+    cards.values().find(|card| {
+        image_file_name(&card.get_name_image_path()) == name
+            || card.images.iter().any(|image| match &image.sources {
+                ImageSource::Files(files) => files.iter().any(|x| image_file_name(x) == name),
+                ImageSource::CardName => false,
+            })
+    })
+}
+
+/// System fonts are only loaded once, the first time a card is rasterized.
+fn fonts() -> Arc<fontdb::Database> {
+    static FONTS: OnceLock<Arc<fontdb::Database>> = OnceLock::new();
+    FONTS
+        .get_or_init(|| {
+            let mut fonts = fontdb::Database::new();
+            fonts.load_system_fonts();
+            Arc::new(fonts)
+        })
+        .clone()
+}
+
+/// Renders an SVG to an image `width` pixels wide.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub fn rasterize(svg: &str, width: u32) -> Result<image::DynamicImage, String> {
+    let options = usvg::Options {
+        fontdb: fonts(),
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_str(svg, &options).map_err(|x| x.to_string())?;
+    let scale = width as f32 / tree.size().width();
+    let height = (tree.size().height() * scale).ceil() as u32;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("Invalid image size")?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|x| {
+            let x = x.demultiply();
+            [x.red(), x.green(), x.blue(), x.alpha()]
+        })
+        .collect();
+    image::RgbaImage::from_raw(width, height, pixels)
+        .map(image::DynamicImage::ImageRgba8)
+        .ok_or_else(|| "Invalid image size".to_string())
+}
+
+/// Renders a card's frame as an SVG, or as PNG or WebP if a `format` or `size` is given.
+pub async fn render_card(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    params: web::Query<ImageParams>,
+    req: HttpRequest,
+) -> io::Result<HttpResponse> {
+    let id = id.into_inner();
+    let svg = data.cards.read().await.get(&id).map(card_svg);
+    let Some(svg) = svg else {
+        return Ok(HttpResponse::BadRequest().body("Not a valid card ID"));
+    };
+
+    if params.format.is_none() && params.size.is_none() {
+        return Ok(HttpResponse::Ok().content_type("image/svg+xml").body(svg));
+    }
+    let encoding = params.format.unwrap_or(Encoding::Png);
+    images::rendered_response(data, &req, id, svg, params.size, encoding).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hemoglobin::cards::rich_text::{RichElement, RichString};
+    use hemoglobin::cards::{Card, Image, ImageSource};
+
+    use super::{card_for_image, layout_text, paragraphs, wrap, Paragraph, ParagraphKind};
+
+    fn rich(elements: Vec<RichElement>) -> RichString {
+        RichString { elements }
+    }
+
+    fn text(text: &str) -> RichElement {
+        RichElement::String(text.to_string())
+    }
+
+    fn describe(paragraph: &Paragraph) -> String {
+        match paragraph.kind {
+            ParagraphKind::Text => paragraph.text.clone(),
+            ParagraphKind::Step(step) => format!("{step}. {}", paragraph.text),
+            ParagraphKind::Flavor => format!("_{}_", paragraph.text),
+        }
+    }
+
+    fn plain(text: &str) -> Paragraph {
+        Paragraph {
+            kind: ParagraphKind::Text,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn splitting_paragraphs() {
+        let description = rich(vec![
+            text("First line\nSecond "),
+            RichElement::SpecificCard {
+                display: "Moth".to_string(),
+                id: "moth".to_string(),
+            },
+            text(" here"),
+            RichElement::LineBreak,
+            text("  \n"),
+            RichElement::Saga(vec![
+                rich(vec![text("Step one")]),
+                rich(vec![text("Step\n   two")]),
+            ]),
+            text("After"),
+        ]);
+        let paragraphs: Vec<String> = paragraphs(&description).iter().map(describe).collect();
+        assert_eq!(
+            paragraphs,
+            [
+                "First line",
+                "Second Moth here",
+                "1. Step one",
+                "2. Step two",
+                "After"
+            ]
+        );
+        assert!(super::paragraphs(&RichString::default()).is_empty());
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(wrap("the quick brown fox", 9), ["the quick", "brown fox"]);
+        assert_eq!(wrap("the  quick", 10), ["the quick"]);
+        // Words that don't fit in a line aren't broken up.
+        assert_eq!(
+            wrap("a extraordinarily b", 5),
+            ["a", "extraordinarily", "b"]
+        );
+        // Widths are in characters, not bytes.
+        assert_eq!(wrap("ä ä ä", 3), ["ä ä", "ä"]);
+        assert!(wrap("   ", 5).is_empty());
+    }
+
+    #[test]
+    fn text_layout() {
+        let short = [plain("Short."), plain("Also short.")];
+        let (size, lines) = layout_text(&short);
+        assert_eq!(size, 30);
+        assert_eq!(lines.len(), 2);
+
+        // Too long for 8 lines of 38 characters at 30, but it fits in 7 lines of 40 at 28.
+        let long = plain(&["word"; 50].join(" "));
+        let (size, lines) = layout_text(std::slice::from_ref(&long));
+        assert_eq!(size, 28);
+        assert_eq!(lines.len(), 7);
+        assert!(lines.iter().all(|(_, _, line)| line.chars().count() <= 40));
+
+        // Text that doesn't fit at any size is laid out at the smallest one.
+        let (size, _) = layout_text(&[plain(&["word"; 500].join(" "))]);
+        assert_eq!(size, 20);
+
+        let step = Paragraph {
+            kind: ParagraphKind::Step(1),
+            text: "Eat.".to_string(),
+        };
+        let saga = [plain("Intro."), step];
+        let (_, lines) = layout_text(&saga);
+        let indents: Vec<usize> = lines.iter().map(|(_, indent, _)| *indent).collect();
+        assert_eq!(indents, [0, 3]);
+    }
+
+    #[test]
+    fn images_of_cards() {
+        let card = |id: &str, name: &str, images: Vec<Image>| Card {
+            id: id.to_string(),
+            name: name.to_string(),
+            images,
+            ..Card::default()
+        };
+        let art = Image {
+            sources: ImageSource::Files(vec!["MothAlt".to_string()]),
+            authors: vec![],
+        };
+        let cards: HashMap<String, Card> = [
+            card("mantis", "Vampire Mantis", vec![]),
+            card("moth", "Moth", vec![art]),
+        ]
+        .into_iter()
+        .map(|x| (x.id.clone(), x))
+        .collect();
+
+        let id = |name| card_for_image(&cards, name).map(|x| x.id.as_str());
+        assert_eq!(id("VampireMantis.png"), Some("mantis"));
+        assert_eq!(id("MothAlt.png"), Some("moth"));
+        // Cards with their own art can still be found by their name.
+        assert_eq!(id("Moth.png"), Some("moth"));
+        assert_eq!(id("Wasp.png"), None);
+        assert_eq!(id("VampireMantis"), None);
+    }
+}
@@ -0,0 +1,49 @@
+use hemoglobin::cards::rich_text::{RichElement, RichString};
+
+/// Collects the IDs of every card a rich text links to, including the ones inside sagas.
+pub fn referenced_ids(string: &RichString) -> Vec<&str> {
+    string
+        .iter()
+        .flat_map(|element| match element {
+            RichElement::SpecificCard { display: _, id } => vec![id.as_str()],
+            RichElement::Saga(steps) => steps.iter().flat_map(referenced_ids).collect(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+/// Collects every search a rich text links to, including the ones inside sagas.
+pub fn searches(string: &RichString) -> Vec<&str> {
+    string
+        .iter()
+        .flat_map(|element| match element {
+            RichElement::CardSearch { display: _, search } => vec![search.as_str()],
+            RichElement::Saga(steps) => steps.iter().flat_map(searches).collect(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hemoglobin::cards::rich_text::RichString;
+    use serde_json::json;
+
+    use super::{referenced_ids, searches};
+
+    #[test]
+    fn links() {
+        let text = json!([
+            "See ",
+            {"display": "the mantis", "id": "mantis"},
+            {"display": "moths", "search": "n:moth"},
+            [[{"display": "an ant", "id": "ant"}], [{"display": "bugs", "search": "k:bug"}]],
+            "\n",
+        ]);
+        // Rich text only deserializes from borrowed strings, so this goes through text.
+        let text: RichString = serde_json::from_str(&text.to_string()).unwrap();
+        assert_eq!(referenced_ids(&text), ["mantis", "ant"]);
+        assert_eq!(searches(&text), ["n:moth", "k:bug"]);
+        assert!(referenced_ids(&RichString::default()).is_empty());
+    }
+}
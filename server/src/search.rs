@@ -1,39 +1,153 @@
 pub mod query_parser;
-use serde::Deserialize;
+use std::cell::Cell;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::cards::{Card, Keyword};
+use actix_web::{web, HttpResponse, Responder};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::cards::{Card, Keyword, KeywordData};
+use crate::AppState;
+
+pub const LEGACY_CARDS_PATH: &str = "./static/legacy_cards.json";
+/// How long a search can take before it's stopped. Regexes and nested `devours` subqueries can make searches slow.
+const SEARCH_TIME_LIMIT: Duration = Duration::from_secs(2);
 
 #[derive(Deserialize)]
 pub struct QueryParams {
     pub query: Option<String>,
 }
 
-#[derive(Deserialize)]
+/// A number cards are compared by: one of their stats, a number written in the query, or a sum of these.
+pub enum Expression {
+    Number(usize),
+    Stat(Box<dyn Fn(&Card) -> usize>),
+    Sum(Vec<Self>),
+}
+
+impl Expression {
+    /// The value of the expression for a card, or `None` if a sum overflows. Comparisons with values that overflow match nothing.
+    pub fn evaluate(&self, card: &Card) -> Option<usize> {
+        match self {
+            Self::Number(x) => Some(*x),
+            Self::Stat(get) => Some(get(card)),
+            Self::Sum(terms) => terms
+                .iter()
+                .try_fold(0, |sum: usize, x| sum.checked_add(x.evaluate(card)?)),
+        }
+    }
+}
+
 pub enum Comparison {
-    GreaterThan(usize),
-    GreaterThanOrEqual(usize),
-    LowerThanOrEqual(usize),
-    Equal(usize),
-    LowerThan(usize),
-    NotEqual(usize),
+    GreaterThan(Expression),
+    GreaterThanOrEqual(Expression),
+    LowerThanOrEqual(Expression),
+    Equal(Expression),
+    LowerThan(Expression),
+    NotEqual(Expression),
+    /// Between two values, including both. A missing value leaves that side open.
+    Between(Option<Expression>, Option<Expression>),
 }
 
 impl Comparison {
-    pub fn compare<T: PartialOrd<usize>>(&self, a: &T) -> bool {
+    /// Compares `a` against this comparison's values, which are read from `card` if they're stats.
+    pub fn compare(&self, a: usize, card: &Card) -> bool {
         match self {
-            Comparison::GreaterThan(x) => a > x,
-            Comparison::Equal(x) => a == x,
-            Comparison::LowerThan(x) => a < x,
-            Comparison::NotEqual(x) => a != x,
-            Comparison::GreaterThanOrEqual(x) => a >= x,
-            Comparison::LowerThanOrEqual(x) => a <= x,
+            Self::GreaterThan(x) => x.evaluate(card).is_some_and(|x| a > x),
+            Self::Equal(x) => x.evaluate(card).is_some_and(|x| a == x),
+            Self::LowerThan(x) => x.evaluate(card).is_some_and(|x| a < x),
+            Self::NotEqual(x) => x.evaluate(card).is_some_and(|x| a != x),
+            Self::GreaterThanOrEqual(x) => x.evaluate(card).is_some_and(|x| a >= x),
+            Self::LowerThanOrEqual(x) => x.evaluate(card).is_some_and(|x| a <= x),
+            Self::Between(low, high) => {
+                low.as_ref()
+                    .is_none_or(|x| x.evaluate(card).is_some_and(|x| a >= x))
+                    && high
+                        .as_ref()
+                        .is_none_or(|x| x.evaluate(card).is_some_and(|x| a <= x))
+            }
         }
     }
 }
 
+/// Where something is in a query, as byte offsets.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Why a query couldn't be parsed, along with the part of the query at fault.
+#[derive(Debug, PartialEq, Eq)]
 pub enum Errors {
-    InvalidComparisonString,
-    UnknownParam,
+    InvalidComparisonString(Span),
+    UnknownParam(String, Span),
+    EmptyParamName(Span),
+    UnknownSubQueryParam(String, Span),
+    InvalidOr(Span),
+    InvalidNegation(Span),
+    UnclosedString(Span),
+    UnclosedRegex(Span),
+    InvalidRegex(String, Span),
+    NonRegexable(String, Span),
+    UnclosedSubquery(Span),
+    UnopenedSubquery(Span),
+    TrailingEscape(Span),
+    TooDeep(Span),
+}
+
+impl Errors {
+    pub const fn span(&self) -> Span {
+        match self {
+            Self::InvalidComparisonString(span)
+            | Self::UnknownParam(_, span)
+            | Self::EmptyParamName(span)
+            | Self::UnknownSubQueryParam(_, span)
+            | Self::InvalidOr(span)
+            | Self::InvalidNegation(span)
+            | Self::UnclosedString(span)
+            | Self::UnclosedRegex(span)
+            | Self::InvalidRegex(_, span)
+            | Self::NonRegexable(_, span)
+            | Self::UnclosedSubquery(span)
+            | Self::UnopenedSubquery(span)
+            | Self::TrailingEscape(span)
+            | Self::TooDeep(span) => *span,
+        }
+    }
+}
+
+impl fmt::Display for Errors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidComparisonString(_) => write!(f, "Not a valid comparison")?,
+            Self::UnknownParam(param, _) => write!(f, "Unknown parameter: {param}")?,
+            Self::EmptyParamName(_) => write!(f, "A parameter needs a name before its :")?,
+            Self::UnknownSubQueryParam(param, _) => {
+                write!(f, "Unknown subquery parameter: {param}")?;
+            }
+            Self::InvalidOr(_) => write!(f, "OR needs a term on each side")?,
+            Self::InvalidNegation(_) => write!(f, "- needs a term right after it")?,
+            Self::UnclosedString(_) => write!(f, "A quote is never closed")?,
+            Self::UnclosedRegex(_) => write!(f, "A regex is never closed")?,
+            Self::InvalidRegex(error, _) => write!(f, "Not a valid regex: {error}")?,
+            Self::NonRegexable(param, _) => write!(f, "{param} can't be searched with a regex")?,
+            Self::UnclosedSubquery(_) => write!(f, "A parenthesis is never closed")?,
+            Self::UnopenedSubquery(_) => write!(f, "A parenthesis is closed but never opened")?,
+            Self::TrailingEscape(_) => write!(f, "A \\ has nothing after it to escape")?,
+            Self::TooDeep(_) => write!(
+                f,
+                "Terms can't be nested more than {} levels deep",
+                query_parser::NESTING_LIMIT
+            )?,
+        }
+        let Span { start, end } = self.span();
+        write!(f, " at {start}..{end}")
+    }
 }
 
 pub fn fuzzy(card: &Card, query: &str) -> bool {
@@ -49,10 +163,372 @@ pub fn fuzzy(card: &Card, query: &str) -> bool {
             .any(|x| x.name.contains(&query.to_lowercase()))
 }
 
+/// Reads a list field of a card, like its kins or keywords.
+type ListGetter<T> = Box<dyn Fn(&Card) -> &[T]>;
+
 pub enum QueryRestriction {
     Fuzzy(String),
-    Comparison(Box<dyn Fn(&Card) -> usize>, Comparison),
+    Comparison(Expression, Comparison),
     Contains(Box<dyn Fn(&Card) -> &str>, String),
-    Has(Box<dyn Fn(&Card) -> &[String]>, String),
-    HasKw(Box<dyn Fn(&Card) -> &[Keyword]>, String),
+    Has(ListGetter<String>, String),
+    HasKw(ListGetter<Keyword>, String),
+    Regex(Box<dyn Fn(&Card) -> &str>, Regex),
+    /// Cards with a `devours` keyword that describes a card matching every restriction.
+    Devours(Vec<Self>),
+    /// Cards that a card matching every restriction devours.
+    DevouredBy(Vec<Self>),
+    /// Cards that don't match every restriction.
+    Not(Vec<Self>),
+    Or(Vec<Self>, Vec<Self>),
+}
+
+/// The cards a card's `devours` keywords describe.
+fn devoured_ids(card: &Card) -> impl Iterator<Item = &crate::cards::CardID> {
+    card.keywords
+        .iter()
+        .filter(|x| x.name == "devours")
+        .filter_map(|x| match &x.data {
+            Some(KeywordData::CardID(id)) => Some(id),
+            _ => None,
+        })
+}
+
+/// What restrictions are checked against besides the card itself.
+pub struct Context<'a> {
+    /// Every card that can be searched, which `devours` and `devouredby` restrictions look through for the other side of the devouring.
+    cards: &'a [Card],
+    deadline: Instant,
+    expired: Cell<bool>,
+}
+
+impl<'a> Context<'a> {
+    pub const fn new(cards: &'a [Card], deadline: Instant) -> Self {
+        Self {
+            cards,
+            deadline,
+            expired: Cell::new(false),
+        }
+    }
+
+    /// Whether the search ran out of time. Once it has, nothing matches anymore, so nested subqueries stop right away.
+    fn expired(&self) -> bool {
+        if !self.expired.get() && Instant::now() > self.deadline {
+            self.expired.set(true);
+        }
+        self.expired.get()
+    }
+
+    /// Every card, until the search runs out of time.
+    fn cards(&self) -> impl Iterator<Item = &'a Card> + '_ {
+        self.cards.iter().take_while(|_| !self.expired())
+    }
+}
+
+impl QueryRestriction {
+    /// Checks a card against this restriction.
+    fn matches(&self, card: &Card, context: &Context) -> bool {
+        match self {
+            Self::Fuzzy(query) => fuzzy(card, query),
+            Self::Comparison(value, comparison) => value
+                .evaluate(card)
+                .is_some_and(|value| comparison.compare(value, card)),
+            Self::Contains(get, value) => get(card).to_lowercase().contains(&value.to_lowercase()),
+            Self::Regex(get, regex) => regex.is_match(get(card)),
+            Self::Has(get, value) => get(card)
+                .iter()
+                .any(|x| x.to_lowercase().contains(&value.to_lowercase())),
+            Self::HasKw(get, value) => get(card)
+                .iter()
+                .any(|x| x.name.to_lowercase().contains(&value.to_lowercase())),
+            Self::Devours(query) => devoured_ids(card).any(|id| {
+                context
+                    .cards()
+                    .any(|devoured| id.describes(devoured) && matches(devoured, query, context))
+            }),
+            Self::DevouredBy(query) => context.cards().any(|devourer| {
+                devoured_ids(devourer).any(|id| id.describes(card))
+                    && matches(devourer, query, context)
+            }),
+            Self::Not(query) => !matches(card, query, context),
+            Self::Or(a, b) => matches(card, a, context) || matches(card, b, context),
+        }
+    }
+}
+
+/// Checks whether a card matches every restriction of a query.
+pub fn matches(card: &Card, query: &[QueryRestriction], context: &Context) -> bool {
+    query.iter().all(|x| x.matches(card, context))
+}
+
+/// Finds the cards that match a query, sorted by name. Gives up and returns `None` if it's still searching at `deadline`.
+pub fn search<'a>(
+    query: &[QueryRestriction],
+    cards: &'a [Card],
+    deadline: Instant,
+) -> Option<Vec<&'a Card>> {
+    let context = Context::new(cards, deadline);
+    let mut results: Vec<&Card> = context
+        .cards()
+        .filter(|card| matches(card, query, &context))
+        .collect();
+    if context.expired() {
+        return None;
+    }
+    results.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+    Some(results)
+}
+
+/// Reads cards in the format used before hemoglobin's, which some datasets still use.
+pub fn load_legacy_cards(path: impl AsRef<Path>) -> Result<Vec<Card>, String> {
+    let data = fs::read_to_string(path).map_err(|x| x.to_string())?;
+    serde_json::from_str(&data).map_err(|x| x.to_string())
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum LegacyResult<'a> {
+    CardList {
+        /// The query as it was understood, with its text escaped the same way every time.
+        query_text: String,
+        content: Vec<&'a Card>,
+    },
+    Error {
+        message: String,
+        span: Span,
+    },
+}
+
+/// Parses and runs a query, which can take a while, so it should be run outside the async workers.
+fn run_legacy_search<'a>(query: &str, cards: &'a [Card]) -> LegacyResult<'a> {
+    let parsed = query_parser::tokenize_query(query)
+        .and_then(|tokens| Ok((query_parser::parse_tokens(&tokens)?, tokens)));
+    let (restrictions, tokens) = match parsed {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            return LegacyResult::Error {
+                message: format!("Query couldn't be parsed: {error}"),
+                span: error.span(),
+            }
+        }
+    };
+    let Some(content) = search(&restrictions, cards, Instant::now() + SEARCH_TIME_LIMIT) else {
+        return LegacyResult::Error {
+            message: format!(
+                "The search took longer than {} seconds and was stopped",
+                SEARCH_TIME_LIMIT.as_secs()
+            ),
+            span: Span {
+                start: 0,
+                end: query.len(),
+            },
+        };
+    };
+    LegacyResult::CardList {
+        query_text: query_parser::print_tokens(&tokens),
+        content,
+    }
+}
+
+pub async fn legacy_search(
+    data: web::Data<AppState>,
+    params: web::Query<QueryParams>,
+) -> impl Responder {
+    let cards = Arc::clone(&data.legacy_cards);
+    let query = params.into_inner().query.unwrap_or_default();
+    let result = web::block(move || {
+        let cards = cards.blocking_read();
+        serde_json::to_string(&run_legacy_search(&query, &cards))
+    })
+    .await;
+    match result {
+        Ok(Ok(json)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json),
+        Ok(Err(x)) => HttpResponse::InternalServerError().body(format!("Couldn't search: {x}")),
+        Err(x) => HttpResponse::InternalServerError().body(format!("Couldn't search: {x}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::query_parser::{parse_tokens, tokenize_query, NESTING_LIMIT};
+    use super::{search, Errors, QueryRestriction, Span};
+    use crate::cards::Card;
+
+    fn query_parser(query: &str) -> Result<Vec<QueryRestriction>, Errors> {
+        parse_tokens(&tokenize_query(query)?)
+    }
+
+    fn cards() -> Vec<Card> {
+        serde_json::from_str(
+            r#"[
+                {"id": "beetle", "name": "Beetle", "description": "A small bug", "cost": 1, "health": 1, "defense": 0, "power": 1, "type": "creature", "kins": ["insect"], "set": "base", "legality": {}},
+                {"id": "mantis", "name": "Mantis", "description": "Eats bugs", "cost": 3, "health": 2, "defense": 1, "power": 3, "type": "creature", "kins": ["insect"], "set": "base", "legality": {},
+                 "keywords": [{"name": "devours", "data": {"type": "CardID", "name": "Beetle"}}]},
+                {"id": "frog", "name": "Frog", "description": "Eats insects", "cost": 2, "health": 2, "defense": 0, "power": 2, "type": "creature", "kins": ["amphibian"], "set": "base", "legality": {},
+                 "keywords": [{"name": "devours", "data": {"type": "CardID", "kins": ["insect"], "power": 3}}]},
+                {"id": "pond", "name": "Pond", "description": "Water", "cost": 0, "health": 0, "defense": 0, "power": 0, "type": "terrain", "set": "base", "legality": {}}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    fn ids(query: &str) -> Vec<String> {
+        let cards = cards();
+        let query = query_parser(query).unwrap();
+        search(&query, &cards, Instant::now() + Duration::from_secs(10))
+            .unwrap()
+            .into_iter()
+            .map(|x| x.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn params_and_words() {
+        assert_eq!(ids("c:>=2 t:creature"), ["frog", "mantis"]);
+        assert_eq!(ids("k:insect eats"), ["mantis"]);
+        assert_eq!(ids("water"), ["pond"]);
+    }
+
+    #[test]
+    fn ranges_and_stats() {
+        assert_eq!(ids("c:1..2"), ["beetle", "frog"]);
+        assert_eq!(ids("c:2.."), ["frog", "mantis"]);
+        assert_eq!(ids("c:..1"), ["beetle", "pond"]);
+        assert_eq!(ids("p>h"), ["mantis"]);
+        assert_eq!(ids("d>=p"), ["pond"]);
+        assert_eq!(ids("c:p..h"), ["beetle", "frog", "pond"]);
+        assert_eq!(ids("stats>4"), ["mantis"]);
+        assert_eq!(ids("h+d>=3"), ["mantis"]);
+        assert_eq!(ids("p+1>c t:terrain"), ["pond"]);
+        // Sums that overflow match nothing, on either side of the comparison.
+        assert!(ids("c:18446744073709551615+1").is_empty());
+        assert!(ids("c:!=18446744073709551615+1").is_empty());
+        // Only Pond costs nothing, so its sum is the only one that doesn't overflow.
+        assert_eq!(ids("18446744073709551615+c>=0"), ["pond"]);
+        assert!(ids("c:18446744073709551615..").is_empty());
+        assert_eq!(
+            ids("c:..18446744073709551615+0"),
+            ["beetle", "frog", "mantis", "pond"]
+        );
+    }
+
+    #[test]
+    fn regexes() {
+        assert_eq!(ids("n:/^m/"), ["mantis"]);
+        assert_eq!(ids("desc:/eats (bugs|insects)$/"), ["frog", "mantis"]);
+        assert_eq!(ids(r"desc:/^\w+ \w+$/ -t:terrain"), ["frog", "mantis"]);
+        assert_eq!(ids("t:/TERRAIN/"), ["pond"]);
+    }
+
+    #[test]
+    fn time_limit() {
+        let cards = cards();
+        let query = query_parser("desc:/a/").unwrap();
+        assert!(search(
+            &query,
+            &cards,
+            Instant::now().checked_sub(Duration::from_secs(1)).unwrap()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn time_limit_stops_nested_subqueries() {
+        // Every card devours every card, so each level of nesting multiplies the work by the number of cards.
+        let cards: Vec<Card> = (0..300)
+            .map(|idx| {
+                serde_json::from_value(serde_json::json!({
+                    "id": format!("bug{idx}"), "name": format!("Bug {idx}"), "description": "",
+                    "cost": 1, "health": 1, "defense": 0, "power": 1, "type": "creature",
+                    "kins": ["insect"], "set": "base", "legality": {},
+                    "keywords": [{"name": "devours", "data": {"type": "CardID", "kins": ["insect"]}}],
+                }))
+                .unwrap()
+            })
+            .collect();
+        let query =
+            query_parser("devours:(devours:(devours:(devours:(devours:(n:nothing)))))").unwrap();
+        let start = Instant::now();
+        assert!(search(&query, &cards, start + Duration::from_millis(50)).is_none());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn devours() {
+        assert_eq!(ids("devours:(n:beetle)"), ["mantis"]);
+        assert_eq!(ids("devours:(k:insect)"), ["frog", "mantis"]);
+        assert_eq!(ids("devouredby:(n:frog)"), ["mantis"]);
+        assert_eq!(ids("devouredby:(devouredby:(k:amphibian))"), ["beetle"]);
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(ids("-t:creature"), ["pond"]);
+        assert_eq!(ids("t:creature -(k:insect c:<2)"), ["frog", "mantis"]);
+        assert_eq!(ids("-devours:(n:beetle) t:creature"), ["beetle", "frog"]);
+    }
+
+    #[test]
+    fn deepest_queries() {
+        let negations = format!("{}t:terrain", "-".repeat(NESTING_LIMIT));
+        assert_eq!(ids(&negations), ["pond"]);
+        let ors = format!("n:pond{}", " OR n:nothing".repeat(NESTING_LIMIT));
+        assert_eq!(ids(&ors), ["pond"]);
+    }
+
+    #[test]
+    fn or() {
+        assert_eq!(ids("n:beetle OR n:pond"), ["beetle", "pond"]);
+        assert_eq!(ids("(c:0 OR c:3) t:creature"), ["mantis"]);
+        assert!(query_parser("OR n:beetle").is_err());
+        assert!(query_parser("n:beetle OR").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let error = |query: &str| query_parser(query).err().unwrap();
+        assert_eq!(
+            error("n:\"beetle"),
+            Errors::UnclosedString(Span { start: 2, end: 9 })
+        );
+        assert_eq!(
+            error("devours:(n:beetle"),
+            Errors::UnclosedSubquery(Span { start: 8, end: 17 })
+        );
+        assert_eq!(
+            error("eats:(n:beetle)"),
+            Errors::UnknownSubQueryParam("eats".to_string(), Span { start: 0, end: 15 })
+        );
+        assert_eq!(
+            error("t:creature c:many"),
+            Errors::InvalidComparisonString(Span { start: 11, end: 17 })
+        );
+        assert_eq!(
+            error("c:1..x"),
+            Errors::InvalidComparisonString(Span { start: 0, end: 6 })
+        );
+        assert_eq!(
+            error("c:.."),
+            Errors::InvalidComparisonString(Span { start: 0, end: 4 })
+        );
+        assert!(matches!(
+            error("n:/(/"),
+            Errors::InvalidRegex(_, Span { start: 0, end: 5 })
+        ));
+        assert!(matches!(
+            error(&format!("n:/{}/", "a{1000}".repeat(20))),
+            Errors::InvalidRegex(..)
+        ));
+        assert!(matches!(
+            error(&format!("n:/{}/", "a".repeat(501))),
+            Errors::InvalidRegex(..)
+        ));
+        assert_eq!(
+            error("k:/insect/"),
+            Errors::NonRegexable("k".to_string(), Span { start: 0, end: 10 })
+        );
+    }
 }
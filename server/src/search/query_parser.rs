@@ -1,175 +1,771 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use regex::{Regex, RegexBuilder};
+
 use crate::cards::Card;
 
-use super::{Comparison, Errors, QueryRestriction};
+use super::{Comparison, Errors, Expression, QueryRestriction, Span};
 
-#[derive(Debug)]
-enum Token {
+#[derive(Debug, PartialEq, Eq)]
+pub enum TokenKind {
     Word(String),
     Param(String, String),
+    /// A parameter with a regex between slashes, like `n:/^vampire/`. The regex is kept as text until it's parsed.
+    RegexParam(String, String),
     SuperParam(String, Vec<Token>),
+    Not(Box<Token>),
+    Group(Vec<Token>),
+    Or(Box<Token>, Box<Token>),
 }
 
-enum TokenMode {
-    Word,
-    Param(String),
-    QParam(String),
-    SParam(String),
+/// A term of a query and where it was read from.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
 }
 
-fn tokenize_query(q: &str) -> Result<Vec<Token>, Errors> {
-    let mut tokens = vec![];
-    let mut word = String::new();
-    let mut mode = TokenMode::Word;
-    let mut paren_count = 0;
-    for ch in q.chars().chain(vec!['\n']) {
-        match mode {
-            TokenMode::Word => match ch {
-                ' ' | '\n' => {
-                    tokens.push(Token::Word(word));
-                    word = String::new();
+/// Characters that mean something in words and parameter names, and have to be escaped with `\` to be read as text.
+const SPECIAL: [char; 10] = ['\\', '(', ')', '"', ':', '-', '<', '>', '=', '!'];
+/// Characters that mean something in parameter values. Comparisons are part of the value, so they don't need escaping there.
+const VALUE_SPECIAL: [char; 5] = ['\\', '(', ')', '"', '/'];
+
+/// How long a regex in a query can be, in bytes.
+const REGEX_LENGTH_LIMIT: usize = 500;
+/// How much memory a compiled regex can take, in bytes.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// How deeply groups and repetitions can nest in a regex.
+const REGEX_NEST_LIMIT: u32 = 20;
+/// How deeply negations, groups, subqueries and `OR`s can nest. Reading, parsing and matching a query all recurse once per level.
+pub const NESTING_LIMIT: usize = 64;
+
+struct Lexer<'a> {
+    query: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    /// How many terms the lexer is inside of.
+    depth: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(query: &'a str) -> Self {
+        Self {
+            query,
+            chars: query.char_indices().peekable(),
+            depth: 0,
+        }
+    }
+
+    fn position(&mut self) -> usize {
+        self.chars.peek().map_or(self.query.len(), |(idx, _)| *idx)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, ch)| *ch)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, ch)| ch.is_whitespace()).is_some() {}
+    }
+
+    /// Whether a term can't start at the next character, because the query or its group ends there.
+    fn at_term_end(&mut self) -> bool {
+        self.peek().is_none_or(|ch| ch.is_whitespace() || ch == ')')
+    }
+
+    /// Reads an `OR` if it's the next term.
+    fn or_keyword(&mut self) -> Option<Span> {
+        let start = self.position();
+        let rest = &self.query[start..];
+        let after = rest.get(2..)?.chars().next();
+        if rest.starts_with("OR") && after.is_none_or(|ch| ch.is_whitespace() || ch == ')') {
+            self.chars.nth(1);
+            Some(Span {
+                start,
+                end: start + 2,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Reads the character after a `\`.
+    fn escaped(&mut self, start: usize) -> Result<char, Errors> {
+        self.chars
+            .next()
+            .map(|(_, ch)| ch)
+            .ok_or(Errors::TrailingEscape(Span {
+                start,
+                end: start + 1,
+            }))
+    }
+
+    /// Whether a parameter's value starts at the next character, either after a `:` or with a comparison like `>`.
+    fn at_value(&mut self) -> bool {
+        let rest = &self.query[self.position()..];
+        rest.starts_with([':', '<', '>', '=']) || rest.starts_with("!=")
+    }
+
+    /// Reads text up to whitespace or a closing parenthesis, and up to where a value starts if `name` is set.
+    fn bare(&mut self, name: bool) -> Result<String, Errors> {
+        let mut text = String::new();
+        while let Some((idx, ch)) = self.chars.peek().copied() {
+            match ch {
+                ch if ch.is_whitespace() => break,
+                ')' => break,
+                _ if name && self.at_value() => break,
+                '\\' => {
+                    self.chars.next();
+                    text.push(self.escaped(idx)?);
                 }
-                ':' => {
-                    mode = TokenMode::Param(word);
-                    word = String::new();
+                ch => {
+                    self.chars.next();
+                    text.push(ch);
                 }
-                ch => word.push(ch),
-            },
-            TokenMode::Param(ref param) => match ch {
-                ' ' | '\n' => {
-                    tokens.push(Token::Param(param.to_string(), word));
-                    word = String::new();
-                    mode = TokenMode::Word;
+            }
+        }
+        Ok(text)
+    }
+
+    /// Reads text between quotes. Quotes and backslashes inside it are escaped with `\`.
+    fn quoted(&mut self) -> Result<String, Errors> {
+        let start = self.position();
+        self.chars.next();
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(text),
+                Some((idx, '\\')) => text.push(self.escaped(idx)?),
+                Some((_, ch)) => text.push(ch),
+                None => {
+                    return Err(Errors::UnclosedString(Span {
+                        start,
+                        end: self.query.len(),
+                    }))
                 }
-                '"' if word.is_empty() => {
-                    mode = TokenMode::QParam(param.to_string());
+            }
+        }
+    }
+
+    /// Reads a regex between slashes. `\/` is a slash that doesn't end the regex, and every other escape is left for the regex.
+    fn regex(&mut self) -> Result<String, Errors> {
+        let start = self.position();
+        self.chars.next();
+        let mut pattern = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '/')) => return Ok(pattern),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, '/')) => pattern.push('/'),
+                    Some((_, ch)) => {
+                        pattern.push('\\');
+                        pattern.push(ch);
+                    }
+                    None => break,
+                },
+                Some((_, ch)) => pattern.push(ch),
+                None => break,
+            }
+        }
+        Err(Errors::UnclosedRegex(Span {
+            start,
+            end: self.query.len(),
+        }))
+    }
+
+    /// Reads the inside of a term that starts at `start` and nests other terms, failing if it's nested too deeply.
+    fn nested<T>(
+        &mut self,
+        start: usize,
+        read: impl FnOnce(&mut Self) -> Result<T, Errors>,
+    ) -> Result<T, Errors> {
+        if self.depth >= NESTING_LIMIT {
+            return Err(Errors::TooDeep(Span {
+                start,
+                end: start + 1,
+            }));
+        }
+        self.depth += 1;
+        let inner = read(self);
+        self.depth -= 1;
+        inner
+    }
+
+    /// Reads terms up to the end of the query or, if a parenthesis was opened at `open`, up to the one that closes it.
+    fn terms(&mut self, open: Option<usize>) -> Result<Vec<Token>, Errors> {
+        let mut tokens: Vec<Token> = vec![];
+        loop {
+            self.skip_whitespace();
+            let start = self.position();
+            match (self.peek(), open) {
+                (None, None) => return Ok(tokens),
+                (None, Some(open)) => {
+                    return Err(Errors::UnclosedSubquery(Span {
+                        start: open,
+                        end: self.query.len(),
+                    }))
                 }
-                '{' if word.is_empty() => {
-                    mode = TokenMode::SParam(param.to_string());
+                (Some(')'), Some(_)) => {
+                    self.chars.next();
+                    return Ok(tokens);
                 }
-                ch => word.push(ch),
-            },
-            TokenMode::QParam(ref param) => match ch {
-                '"' => {
-                    tokens.push(Token::Param(param.to_string(), word));
-                    word = String::new();
-                    mode = TokenMode::Word;
+                (Some(')'), None) => {
+                    return Err(Errors::UnopenedSubquery(Span {
+                        start,
+                        end: start + 1,
+                    }))
                 }
-                ch => word.push(ch),
-            },
-            TokenMode::SParam(ref param) => match ch {
-                ')' if paren_count == 0 => {
-                    tokens.push(Token::SuperParam(param.to_string(), tokenize_query(&word)?));
-                    word = String::new();
-                    mode = TokenMode::Word;
+                _ => (),
+            }
+
+            if let Some(or) = self.or_keyword() {
+                let left = tokens.pop().ok_or(Errors::InvalidOr(or))?;
+                self.skip_whitespace();
+                if self.at_term_end() || self.or_keyword().is_some() {
+                    return Err(Errors::InvalidOr(or));
+                }
+                let right = self.term()?;
+                let span = Span {
+                    start: left.span.start,
+                    end: right.span.end,
+                };
+                // `OR`s chain to the left, so they can nest deeply without the lexer recursing.
+                if self.depth + depth(&left).max(depth(&right)) >= NESTING_LIMIT {
+                    return Err(Errors::TooDeep(span));
                 }
-                '(' => {
-                    paren_count += 1;
-                    word.push(ch);
+                tokens.push(Token {
+                    span,
+                    kind: TokenKind::Or(Box::new(left), Box::new(right)),
+                });
+            } else {
+                tokens.push(self.term()?);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Token, Errors> {
+        let start = self.position();
+        let kind = match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                if self.at_term_end() {
+                    return Err(Errors::InvalidNegation(Span {
+                        start,
+                        end: start + 1,
+                    }));
                 }
-                ')' if paren_count > 0 => {
-                    paren_count -= 1;
-                    word.push(ch);
+                TokenKind::Not(Box::new(self.nested(start, Self::term)?))
+            }
+            Some('(') => {
+                self.chars.next();
+                TokenKind::Group(self.nested(start, |lexer| lexer.terms(Some(start)))?)
+            }
+            Some('"') => TokenKind::Word(self.quoted()?),
+            _ => {
+                let text = self.bare(true)?;
+                let colon = self.position();
+                match self.peek() {
+                    Some(_) if text.is_empty() && self.at_value() => {
+                        return Err(Errors::EmptyParamName(Span {
+                            start,
+                            end: colon + 1,
+                        }))
+                    }
+                    Some(':') => {
+                        self.chars.next();
+                        match self.peek() {
+                            Some('(') => {
+                                self.chars.next();
+                                let terms =
+                                    self.nested(start, |lexer| lexer.terms(Some(colon + 1)))?;
+                                TokenKind::SuperParam(text, terms)
+                            }
+                            Some('"') => TokenKind::Param(text, self.quoted()?),
+                            Some('/') => TokenKind::RegexParam(text, self.regex()?),
+                            _ => TokenKind::Param(text, self.bare(false)?),
+                        }
+                    }
+                    Some(_) if self.at_value() => TokenKind::Param(text, self.bare(false)?),
+                    _ => TokenKind::Word(text),
                 }
-                ch => word.push(ch),
+            }
+        };
+        Ok(Token {
+            kind,
+            span: Span {
+                start,
+                end: self.position(),
             },
+        })
+    }
+}
+
+/// How many levels of terms a token nests, which the lexer already limited, so this can't recurse too deeply.
+fn depth(token: &Token) -> usize {
+    match &token.kind {
+        TokenKind::Word(_) | TokenKind::Param(..) | TokenKind::RegexParam(..) => 0,
+        TokenKind::Not(token) => depth(token) + 1,
+        TokenKind::Group(tokens) | TokenKind::SuperParam(_, tokens) => {
+            tokens.iter().map(depth).max().unwrap_or(0) + 1
+        }
+        TokenKind::Or(a, b) => depth(a).max(depth(b)) + 1,
+    }
+}
+
+/// Splits a query into terms. Text can be quoted, or have the characters that mean something in queries escaped with `\`.
+pub fn tokenize_query(q: &str) -> Result<Vec<Token>, Errors> {
+    Lexer::new(q).terms(None)
+}
+
+/// Writes text so it's read back as is, quoting it if it has whitespace and escaping `special` characters otherwise.
+fn print_text(text: &str, special: &[char]) -> String {
+    if text.is_empty() || text.chars().any(char::is_whitespace) {
+        let mut quoted = String::from('"');
+        for ch in text.chars() {
+            if matches!(ch, '"' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(ch);
+        }
+        quoted.push('"');
+        quoted
+    } else {
+        print_bare(text, special)
+    }
+}
+
+/// Writes text so it's read back as is without quotes, which parameter names can't have.
+fn print_bare(text: &str, special: &[char]) -> String {
+    if text == "OR" {
+        return String::from("\\OR");
+    }
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        if special.contains(&ch) || ch.is_whitespace() {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn print_token(token: &Token) -> String {
+    match &token.kind {
+        TokenKind::Word(word) => print_text(word, &SPECIAL),
+        TokenKind::Param(param, value) => format!(
+            "{}:{}",
+            print_bare(param, &SPECIAL),
+            print_text(value, &VALUE_SPECIAL)
+        ),
+        TokenKind::RegexParam(param, pattern) => format!(
+            "{}:/{}/",
+            print_bare(param, &SPECIAL),
+            pattern.replace('/', "\\/")
+        ),
+        TokenKind::SuperParam(param, tokens) => {
+            format!("{}:({})", print_bare(param, &SPECIAL), print_tokens(tokens))
         }
+        TokenKind::Not(token) => format!("-{}", print_token(token)),
+        TokenKind::Group(tokens) => format!("({})", print_tokens(tokens)),
+        TokenKind::Or(a, b) => format!("{} OR {}", print_token(a), print_token(b)),
     }
-    Ok(tokens)
 }
 
-pub fn query_parser(q: &str) -> Result<Vec<QueryRestriction>, Errors> {
-    let q = tokenize_query(q)?;
+/// Writes tokens back as a query that tokenizes to the same tokens.
+pub fn print_tokens(tokens: &[Token]) -> String {
+    tokens.iter().map(print_token).collect::<Vec<_>>().join(" ")
+}
+
+/// Turns tokens into the restrictions a card has to match.
+pub fn parse_tokens(q: &[Token]) -> Result<Vec<QueryRestriction>, Errors> {
     let mut restrictions = vec![];
     let mut string = String::new();
-    for word in &q {
-        match word {
-            Token::Word(x) => {
+    for Token { kind, span } in q {
+        match kind {
+            TokenKind::Word(x) => {
                 string.push_str(x);
                 string.push(' ');
             }
-            Token::Param(param, value) => match param.as_str() {
-                "cost" | "c" => {
-                    let cmp = text_comparison_parser(value)?;
-                    restrictions.push(QueryRestriction::Comparison(Box::new(Card::get_cost), cmp));
-                }
-                "health" | "h" | "hp" => {
-                    let cmp = text_comparison_parser(value)?;
-                    restrictions.push(QueryRestriction::Comparison(
-                        Box::new(Card::get_health),
-                        cmp,
-                    ));
-                }
-                "power" | "strength" | "damage" | "p" | "dmg" | "str" => {
-                    let cmp = text_comparison_parser(value)?;
-                    restrictions.push(QueryRestriction::Comparison(Box::new(Card::get_power), cmp));
-                }
-                "defense" | "def" | "d" => {
-                    let cmp = text_comparison_parser(value)?;
-                    restrictions.push(QueryRestriction::Comparison(
-                        Box::new(Card::get_defense),
-                        cmp,
-                    ));
-                }
-                "name" | "n" => restrictions.push(QueryRestriction::Contains(
-                    Box::new(Card::get_name),
-                    value.clone(),
-                )),
-                "type" | "t" => restrictions.push(QueryRestriction::Contains(
-                    Box::new(Card::get_type),
-                    value.clone(),
-                )),
-                "kin" | "k" => restrictions.push(QueryRestriction::Has(
-                    Box::new(Card::get_kins),
-                    value.clone(),
-                )),
-                "keyword" | "kw" => restrictions.push(QueryRestriction::HasKw(
-                    Box::new(Card::get_keywords),
-                    value.clone(),
+            TokenKind::Param(param, value) => {
+                match (expression(param), text_field(param), param.as_str()) {
+                    (Some(stat), _, _) => {
+                        let cmp = text_comparison_parser(value, *span)?;
+                        restrictions.push(QueryRestriction::Comparison(stat, cmp));
+                    }
+                    (None, Some(get), _) => {
+                        restrictions.push(QueryRestriction::Contains(Box::new(get), value.clone()));
+                    }
+                    (None, None, "kin" | "k") => restrictions.push(QueryRestriction::Has(
+                        Box::new(Card::get_kins),
+                        value.clone(),
+                    )),
+                    (None, None, "keyword" | "kw") => restrictions.push(QueryRestriction::HasKw(
+                        Box::new(Card::get_keywords),
+                        value.clone(),
+                    )),
+                    (None, None, param) => {
+                        return Err(Errors::UnknownParam(param.to_owned(), *span))
+                    }
+                }
+            }
+            TokenKind::RegexParam(param, pattern) => match text_field(param) {
+                Some(get) => restrictions.push(QueryRestriction::Regex(
+                    Box::new(get),
+                    regex(pattern, *span)?,
                 )),
-                _ => return Err(Errors::UnknownParam),
+                None => return Err(Errors::NonRegexable(param.clone(), *span)),
             },
-            Token::SuperParam(param, value) => {
-                if param == "devour" {
-                    todo!();
+            TokenKind::SuperParam(param, value) => match param.as_str() {
+                "devours" | "devour" | "dev" => {
+                    restrictions.push(QueryRestriction::Devours(parse_tokens(value)?));
                 }
-            }
+                "devouredby" | "devby" | "dby" | "db" => {
+                    restrictions.push(QueryRestriction::DevouredBy(parse_tokens(value)?));
+                }
+                param => return Err(Errors::UnknownSubQueryParam(param.to_owned(), *span)),
+            },
+            TokenKind::Not(token) => restrictions.push(QueryRestriction::Not(parse_tokens(
+                std::slice::from_ref(token),
+            )?)),
+            TokenKind::Group(tokens) => restrictions.extend(parse_tokens(tokens)?),
+            TokenKind::Or(a, b) => restrictions.push(QueryRestriction::Or(
+                parse_tokens(std::slice::from_ref(a))?,
+                parse_tokens(std::slice::from_ref(b))?,
+            )),
         }
     }
     let string = string.trim().to_string();
-    restrictions.push(QueryRestriction::Fuzzy(string));
+    if !string.is_empty() {
+        restrictions.push(QueryRestriction::Fuzzy(string));
+    }
     Ok(restrictions)
 }
 
-fn text_comparison_parser(s: &str) -> Result<Comparison, Errors> {
-    match s.parse::<usize>() {
-        Ok(x) => Ok(Comparison::Equal(x)),
-        Err(_) => {
-            if let Some(end) = s.strip_prefix(">=") {
-                end.parse::<usize>()
-                    .map(Comparison::GreaterThanOrEqual)
-                    .map_err(|_| Errors::InvalidComparisonString)
-            } else if let Some(end) = s.strip_prefix("<=") {
-                end.parse::<usize>()
-                    .map(Comparison::LowerThanOrEqual)
-                    .map_err(|_| Errors::InvalidComparisonString)
-            } else if let Some(end) = s.strip_prefix('>') {
-                end.parse::<usize>()
-                    .map(Comparison::GreaterThan)
-                    .map_err(|_| Errors::InvalidComparisonString)
-            } else if let Some(end) = s.strip_prefix('<') {
-                end.parse::<usize>()
-                    .map(Comparison::LowerThan)
-                    .map_err(|_| Errors::InvalidComparisonString)
-            } else if let Some(end) = s.strip_prefix('=') {
-                end.parse::<usize>()
-                    .map(Comparison::Equal)
-                    .map_err(|_| Errors::InvalidComparisonString)
-            } else if let Some(end) = s.strip_prefix("!=") {
-                end.parse::<usize>()
-                    .map(Comparison::NotEqual)
-                    .map_err(|_| Errors::InvalidComparisonString)
-            } else {
-                Err(Errors::InvalidComparisonString)
+/// The text a parameter searches in, for parameters that search text.
+fn text_field(param: &str) -> Option<fn(&Card) -> &str> {
+    match param {
+        "name" | "n" => Some(Card::get_name),
+        "type" | "t" => Some(Card::get_type),
+        "description" | "desc" => Some(Card::get_description),
+        _ => None,
+    }
+}
+
+/// Compiles a regex from a query, ignoring case like other text searches do. Regexes that would take too much memory are rejected, and the regex engine never backtracks, so matching takes time proportional to the text.
+fn regex(pattern: &str, span: Span) -> Result<Regex, Errors> {
+    if pattern.len() > REGEX_LENGTH_LIMIT {
+        return Err(Errors::InvalidRegex(
+            format!("Regexes can be at most {REGEX_LENGTH_LIMIT} bytes long"),
+            span,
+        ));
+    }
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .nest_limit(REGEX_NEST_LIMIT)
+        .build()
+        .map_err(|x| Errors::InvalidRegex(x.to_string(), span))
+}
+
+/// Reads a stat, a number, or a sum of them like `h+d`. `stats` is the sum of a card's health, defense and power.
+fn expression(s: &str) -> Option<Expression> {
+    let stat = |get: fn(&Card) -> usize| Expression::Stat(Box::new(get));
+    let mut terms = s
+        .split('+')
+        .map(|term| match term {
+            "cost" | "c" => Some(stat(Card::get_cost)),
+            "health" | "h" | "hp" => Some(stat(Card::get_health)),
+            "power" | "strength" | "damage" | "p" | "dmg" | "str" => Some(stat(Card::get_power)),
+            "defense" | "def" | "d" => Some(stat(Card::get_defense)),
+            "stats" => Some(Expression::Sum(vec![
+                stat(Card::get_health),
+                stat(Card::get_defense),
+                stat(Card::get_power),
+            ])),
+            term => term.parse().ok().map(Expression::Number),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if terms.len() == 1 {
+        terms.pop()
+    } else {
+        Some(Expression::Sum(terms))
+    }
+}
+
+/// Reads a comparison like `>=2`, `<p` or `h+d`, or a range like `1..3`, `..p` or `2..`.
+fn text_comparison_parser(s: &str, span: Span) -> Result<Comparison, Errors> {
+    let value = |s: &str| expression(s).ok_or(Errors::InvalidComparisonString(span));
+    if let Some((low, high)) = s.split_once("..") {
+        if low.is_empty() && high.is_empty() {
+            return Err(Errors::InvalidComparisonString(span));
+        }
+        let bound = |s: &str| (!s.is_empty()).then(|| value(s)).transpose();
+        return Ok(Comparison::Between(bound(low)?, bound(high)?));
+    }
+    let operator = [">=", "<=", "!=", ">", "<", "="]
+        .into_iter()
+        .find(|x| s.starts_with(x))
+        .unwrap_or_default();
+    let value = value(&s[operator.len()..])?;
+    Ok(match operator {
+        ">=" => Comparison::GreaterThanOrEqual(value),
+        "<=" => Comparison::LowerThanOrEqual(value),
+        "!=" => Comparison::NotEqual(value),
+        ">" => Comparison::GreaterThan(value),
+        "<" => Comparison::LowerThan(value),
+        _ => Comparison::Equal(value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::{
+        parse_tokens, print_token, print_tokens, tokenize_query, Token, TokenKind, NESTING_LIMIT,
+    };
+    use crate::search::{Errors, Span};
+
+    const fn span(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    fn token(kind: TokenKind) -> Token {
+        Token {
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    /// Drops where tokens were read from, so they can be compared with generated ones.
+    fn without_spans(tokens: Vec<Token>) -> Vec<Token> {
+        tokens.into_iter().map(without_span).collect()
+    }
+
+    fn without_span(token: Token) -> Token {
+        self::token(match token.kind {
+            TokenKind::SuperParam(param, tokens) => {
+                TokenKind::SuperParam(param, without_spans(tokens))
+            }
+            TokenKind::Not(token) => TokenKind::Not(Box::new(without_span(*token))),
+            TokenKind::Group(tokens) => TokenKind::Group(without_spans(tokens)),
+            TokenKind::Or(a, b) => {
+                TokenKind::Or(Box::new(without_span(*a)), Box::new(without_span(*b)))
+            }
+            kind => kind,
+        })
+    }
+
+    fn words(query: &str) -> Vec<String> {
+        tokenize_query(query)
+            .unwrap()
+            .into_iter()
+            .map(|x| match x.kind {
+                TokenKind::Word(word) => word,
+                kind => panic!("{kind:?} isn't a word"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn whitespace_and_escapes() {
+        assert_eq!(words("  a   b\tc "), ["a", "b", "c"]);
+        assert_eq!(words(r#""a \"b\"" c\ d \\"#), [r#"a "b""#, "c d", "\\"]);
+        assert_eq!(words(r"\-a \(b\) \OR"), ["-a", "(b)", "OR"]);
+        assert_eq!(
+            without_spans(tokenize_query(r#"n:"a b" k:c\:d"#).unwrap()),
+            [
+                token(TokenKind::Param("n".into(), "a b".into())),
+                token(TokenKind::Param("k".into(), "c:d".into()))
+            ]
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(
+            without_spans(tokenize_query("p>h stats>=6 c:1..3 c!=2").unwrap()),
+            [
+                token(TokenKind::Param("p".into(), ">h".into())),
+                token(TokenKind::Param("stats".into(), ">=6".into())),
+                token(TokenKind::Param("c".into(), "1..3".into())),
+                token(TokenKind::Param("c".into(), "!=2".into()))
+            ]
+        );
+        assert_eq!(words(r"wow! a\=b"), ["wow!", "a=b"]);
+        assert_eq!(
+            tokenize_query(">3").err(),
+            Some(Errors::EmptyParamName(span(0, 1)))
+        );
+    }
+
+    #[test]
+    fn regexes() {
+        assert_eq!(
+            without_spans(tokenize_query(r"n:/^a b\/c\d/ n:\/x").unwrap()),
+            [
+                token(TokenKind::RegexParam("n".into(), r"^a b/c\d".into())),
+                token(TokenKind::Param("n".into(), "/x".into()))
+            ]
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = tokenize_query("ant -(c:1 OR k:\"é x\") dev:(b)").unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|x| x.span).collect();
+        assert_eq!(spans, [span(0, 3), span(4, 22), span(23, 30)]);
+        let TokenKind::Not(group) = &tokens[1].kind else {
+            panic!()
+        };
+        let TokenKind::Group(or) = &group.kind else {
+            panic!()
+        };
+        assert_eq!(or[0].span, span(6, 21));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |query| tokenize_query(query).err().unwrap();
+        assert_eq!(error("a \"b c"), Errors::UnclosedString(span(2, 6)));
+        assert_eq!(error("k:\"b"), Errors::UnclosedString(span(2, 4)));
+        assert_eq!(error("a (b (c)"), Errors::UnclosedSubquery(span(2, 8)));
+        assert_eq!(error("dev:(a"), Errors::UnclosedSubquery(span(4, 6)));
+        assert_eq!(error("a) b"), Errors::UnopenedSubquery(span(1, 2)));
+        assert_eq!(error("a\\"), Errors::TrailingEscape(span(1, 2)));
+        assert_eq!(error("OR a"), Errors::InvalidOr(span(0, 2)));
+        assert_eq!(error("a OR"), Errors::InvalidOr(span(2, 4)));
+        assert_eq!(error("(a OR) b"), Errors::InvalidOr(span(3, 5)));
+        assert_eq!(error("a OR OR b"), Errors::InvalidOr(span(2, 4)));
+        assert_eq!(error("a - b"), Errors::InvalidNegation(span(2, 3)));
+        assert_eq!(error("a :b"), Errors::EmptyParamName(span(2, 3)));
+        assert_eq!(error("n:/a\\/b"), Errors::UnclosedRegex(span(2, 7)));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let error = |query: &str| tokenize_query(query).err().unwrap();
+        // These used to overflow the stack.
+        assert_eq!(
+            error(&format!("{}a", "-".repeat(30_000))),
+            Errors::TooDeep(span(NESTING_LIMIT, NESTING_LIMIT + 1))
+        );
+        assert_eq!(
+            error(&"(".repeat(30_000)),
+            Errors::TooDeep(span(NESTING_LIMIT, NESTING_LIMIT + 1))
+        );
+        assert_eq!(
+            error(&"dev:(".repeat(30_000)),
+            Errors::TooDeep(span(5 * NESTING_LIMIT, 5 * NESTING_LIMIT + 1))
+        );
+        let ors = format!("a{}", " OR a".repeat(30_000));
+        assert_eq!(
+            error(&ors),
+            Errors::TooDeep(span(0, 1 + 5 * (NESTING_LIMIT + 1)))
+        );
+
+        // Up to the limit is fine, however the levels are mixed.
+        let deepest = format!("{}a", "-".repeat(NESTING_LIMIT));
+        assert!(parse_tokens(&tokenize_query(&deepest).unwrap()).is_ok());
+        assert!(tokenize_query(&format!("a{}", " OR a".repeat(NESTING_LIMIT))).is_ok());
+        let half = NESTING_LIMIT / 2;
+        let mixed = format!("{}a{}", "-(".repeat(half), ")".repeat(half));
+        assert!(tokenize_query(&mixed).is_ok());
+        assert!(tokenize_query(&format!("({mixed})")).is_err());
+        assert!(tokenize_query(&format!("b OR {mixed}")).is_err());
+        assert!(tokenize_query(&format!("({mixed}) OR b")).is_err());
+    }
+
+    /// Text with every character that means something in a query.
+    fn text(rng: &mut StdRng) -> String {
+        const CHARS: [char; 18] = [
+            'a', 'b', 'O', 'R', 'é', '(', ')', '"', ':', '-', '\\', ' ', '\t', '<', '>', '=', '!',
+            '/',
+        ];
+        let length = rng.gen_range(0..6);
+        (0..length)
+            .map(|_| CHARS[rng.gen_range(0..CHARS.len())])
+            .collect()
+    }
+
+    fn random_tokens(rng: &mut StdRng, depth: usize) -> Vec<Token> {
+        (0..rng.gen_range(0..4))
+            .map(|_| random_token(rng, depth, true))
+            .collect()
+    }
+
+    /// A random token. `OR` reads the term right after it, so an `Or` can only be the first side of another `Or`.
+    fn random_token(rng: &mut StdRng, depth: usize, allow_or: bool) -> Token {
+        let kinds = match (depth, allow_or) {
+            (0, _) => 3,
+            (_, false) => 6,
+            (_, true) => 7,
+        };
+        token(match rng.gen_range(0..kinds) {
+            0 => TokenKind::Word(text(rng)),
+            1 => TokenKind::Param(text(rng) + "p", text(rng)),
+            // Regexes keep their escapes, so a `\` can't be last.
+            2 => TokenKind::RegexParam(text(rng) + "p", text(rng).replace('\\', "")),
+            3 => TokenKind::SuperParam(text(rng) + "p", random_tokens(rng, depth - 1)),
+            4 => TokenKind::Not(Box::new(random_token(rng, depth - 1, false))),
+            5 => TokenKind::Group(random_tokens(rng, depth - 1)),
+            _ => TokenKind::Or(
+                Box::new(random_token(rng, depth - 1, true)),
+                Box::new(random_token(rng, depth - 1, false)),
+            ),
+        })
+    }
+
+    /// Checks that every token was read from the text it prints as.
+    fn check_spans(query: &str, tokens: &[Token]) {
+        for token in tokens {
+            assert_eq!(&query[token.span.start..token.span.end], print_token(token));
+            match &token.kind {
+                TokenKind::SuperParam(_, tokens) | TokenKind::Group(tokens) => {
+                    check_spans(query, tokens);
+                }
+                TokenKind::Not(token) => check_spans(query, std::slice::from_ref(token)),
+                TokenKind::Or(a, b) => {
+                    check_spans(query, std::slice::from_ref(a));
+                    check_spans(query, std::slice::from_ref(b));
+                }
+                TokenKind::Word(_) | TokenKind::Param(..) | TokenKind::RegexParam(..) => (),
+            }
+        }
+    }
+
+    #[test]
+    fn printed_tokens_round_trip() {
+        for seed in 0..2000 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let tokens = random_tokens(&mut rng, 3);
+            let query = print_tokens(&tokens);
+            let read = tokenize_query(&query)
+                .unwrap_or_else(|x| panic!("{query:?} couldn't be read: {x}"));
+            check_spans(&query, &read);
+            assert_eq!(without_spans(read), tokens, "{query:?}");
+        }
+    }
+
+    #[test]
+    fn any_query_reads_or_fails_cleanly() {
+        for seed in 0..5000 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let query: String = (0..rng.gen_range(0..4)).map(|_| text(&mut rng)).collect();
+            match tokenize_query(&query) {
+                Ok(tokens) => {
+                    let printed = print_tokens(&tokens);
+                    let reread = tokenize_query(&printed)
+                        .unwrap_or_else(|x| panic!("{query:?} -> {printed:?}: {x}"));
+                    assert_eq!(without_spans(reread), without_spans(tokens), "{query:?}");
+                }
+                Err(error) => {
+                    let Span { start, end } = error.span();
+                    assert!(start < end && end <= query.len(), "{query:?}: {error}");
+                    assert!(query.is_char_boundary(start) && query.is_char_boundary(end));
+                }
             }
         }
     }
@@ -0,0 +1,245 @@
+use std::collections::HashSet;
+
+use actix_web::{web, HttpResponse, Responder};
+use hemoglobin::cards::properties::{Number, Read};
+use hemoglobin::cards::Card;
+use hemoglobin::numbers::{MaybeImprecise, MaybeVar};
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+
+const DEFAULT_LIMIT: usize = 12;
+const MAX_LIMIT: usize = 50;
+/// Description words shorter than this are too common to say anything about a card.
+const MIN_TERM_LENGTH: usize = 4;
+
+#[derive(Deserialize)]
+pub struct SimilarParams {
+    /// How many cards to return.
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Similar<'a> {
+    card: &'a Card,
+    score: f64,
+    /// Why the card was picked, made from the things it has most in common with the original.
+    reason: String,
+}
+
+fn constant(card: &Card, property: Number) -> Option<usize> {
+    match card.get_num_property(&property) {
+        Some(MaybeImprecise::Precise(MaybeVar::Const(value))) => Some(value),
+        _ => None,
+    }
+}
+
+/// Gets closer to 1 the smaller `distance` is.
+fn closeness(distance: f64) -> f64 {
+    1.0 / (1.0 + distance)
+}
+
+fn description_terms(card: &Card) -> HashSet<String> {
+    card.description
+        .to_string()
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| x.chars().count() >= MIN_TERM_LENGTH)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The indefinite article that goes before `word`.
+fn article(word: &str) -> &'static str {
+    if word.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        "an"
+    } else {
+        "a"
+    }
+}
+
+fn shared<'a>(a: impl Iterator<Item = &'a String>, b: &[&String]) -> Vec<&'a str> {
+    let mut shared: Vec<&str> = a.filter(|x| b.contains(x)).map(String::as_str).collect();
+    shared.sort_unstable();
+    shared.dedup();
+    shared
+}
+
+/// Scores how similar `other` is to `card`. Each thing they have in common adds to the score, and comes with a reason.
+#[allow(clippy::cast_precision_loss)]
+fn score(card: &Card, terms: &HashSet<String>, other: &Card) -> (f64, Vec<(f64, String)>) {
+    let mut reasons = vec![];
+
+    let kins = shared(other.kins.iter(), &card.kins.iter().collect::<Vec<_>>());
+    if !kins.is_empty() {
+        reasons.push((
+            3.0 * kins.len() as f64,
+            format!("Shares kins: {}", kins.join(", ")),
+        ));
+    }
+    let keywords = shared(
+        other.keywords.iter().map(|x| &x.name),
+        &card.keywords.iter().map(|x| &x.name).collect::<Vec<_>>(),
+    );
+    if !keywords.is_empty() {
+        reasons.push((
+            2.0 * keywords.len() as f64,
+            format!("Shares keywords: {}", keywords.join(", ")),
+        ));
+    }
+    if card.r#type.eq_ignore_ascii_case(&other.r#type) {
+        let r#type = card.r#type.to_lowercase();
+        reasons.push((2.0, format!("Also {} {type}", article(&r#type))));
+    }
+
+    if let (Some(a), Some(b)) = (constant(card, Number::Cost), constant(other, Number::Cost)) {
+        let reason = match b.cmp(&a) {
+            std::cmp::Ordering::Equal => "Same cost".to_string(),
+            std::cmp::Ordering::Greater => format!("Costs {} more", b - a),
+            std::cmp::Ordering::Less => format!("Costs {} less", a - b),
+        };
+        reasons.push((2.0 * closeness(a.abs_diff(b) as f64), reason));
+    }
+
+    let stats = [Number::Health, Number::Defense, Number::Power];
+    let profile = |card| stats.map(|stat| constant(card, stat));
+    if let (Some(a), Some(b)) = (
+        profile(card).into_iter().collect::<Option<Vec<_>>>(),
+        profile(other).into_iter().collect::<Option<Vec<_>>>(),
+    ) {
+        let distance = a
+            .iter()
+            .zip(&b)
+            .map(|(a, b)| (a.abs_diff(*b) as f64).powi(2))
+            .sum::<f64>()
+            .sqrt();
+        reasons.push((2.0 * closeness(distance), "Similar stats".to_string()));
+    }
+
+    let other_terms = description_terms(other);
+    let union = terms.union(&other_terms).count();
+    if union > 0 {
+        let overlap = terms.intersection(&other_terms).count() as f64 / union as f64;
+        if overlap > 0.0 {
+            reasons.push((4.0 * overlap, "Similar description".to_string()));
+        }
+    }
+
+    let score = reasons.iter().map(|(score, _)| score).sum();
+    (score, reasons)
+}
+
+pub async fn similar_cards(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    params: web::Query<SimilarParams>,
+) -> impl Responder {
+    let cards = data.cards.read().await;
+    let Some(card) = cards.get(id.as_str()) else {
+        return HttpResponse::NotFound().body("Not a valid card ID");
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let terms = description_terms(card);
+
+    let mut similar: Vec<Similar> = cards
+        .values()
+        .filter(|other| other.id != card.id)
+        .filter_map(|other| {
+            let (score, mut reasons) = score(card, &terms, other);
+            reasons.sort_by(|a, b| b.0.total_cmp(&a.0));
+            (score > 0.0).then(|| Similar {
+                card: other,
+                score,
+                reason: reasons
+                    .into_iter()
+                    .take(2)
+                    .map(|(_, reason)| reason)
+                    .collect::<Vec<_>>()
+                    .join(". "),
+            })
+        })
+        .collect();
+    similar.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.card.id.cmp(&b.card.id))
+    });
+    similar.truncate(limit);
+
+    HttpResponse::Ok().json(similar)
+}
+
+#[cfg(test)]
+mod tests {
+    use hemoglobin::cards::Card;
+    use serde_json::{json, Value};
+
+    use super::{description_terms, score};
+
+    fn card(value: &Value) -> Card {
+        let mut card = json!({
+            "id": "card", "name": "Card", "description": "", "cost": 1, "health": 1,
+            "defense": 0, "power": 1, "type": "creature", "legality": {},
+        });
+        card.as_object_mut()
+            .unwrap()
+            .extend(value.as_object().unwrap().clone());
+        serde_json::from_str(&card.to_string()).unwrap()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn scored(a: &Card, b: &Card) -> (f64, Vec<(f64, String)>) {
+        score(a, &description_terms(a), b)
+    }
+
+    #[test]
+    fn scoring() {
+        let mantis = card(&json!({
+            "kins": ["bug", "vampire"], "keywords": [{"name": "Flying"}], "cost": 2,
+            "health": 1, "power": 3, "description": "Drains blood from every enemy.",
+        }));
+        let mosquito = card(&json!({
+            "kins": ["bug"], "keywords": [{"name": "Flying"}], "cost": 3, "health": 1,
+            "power": 3, "description": "Drinks blood.",
+        }));
+        let (total, reasons) = scored(&mantis, &mosquito);
+        let reasons: Vec<(f64, &str)> = reasons.iter().map(|(x, y)| (*x, y.as_str())).collect();
+        let expected = [
+            (3.0, "Shares kins: bug"),
+            (2.0, "Shares keywords: Flying"),
+            (2.0, "Also a creature"),
+            (1.0, "Costs 1 more"),
+            (2.0, "Similar stats"),
+            // One of six description words is shared.
+            (4.0 / 6.0, "Similar description"),
+        ];
+        assert_eq!(reasons.len(), expected.len());
+        for ((score, reason), (expected_score, expected_reason)) in reasons.iter().zip(expected) {
+            assert!(close(*score, expected_score), "{reason}: {score}");
+            assert_eq!(*reason, expected_reason);
+        }
+        assert!(close(total, 10.0 + 4.0 / 6.0));
+    }
+
+    #[test]
+    fn unrelated_cards() {
+        let command = card(&json!({"type": "command", "cost": 5, "description": "Heal."}));
+        let insect = card(&json!({"type": "insect", "cost": 0, "kins": ["bug"]}));
+        // Commands have no stats to compare, and costs further apart score less.
+        let (total, reasons) = scored(&command, &insect);
+        assert_eq!(reasons.len(), 1);
+        assert_eq!(reasons[0].1, "Costs 5 less");
+        assert!(close(total, 2.0 / 6.0));
+    }
+
+    #[test]
+    fn articles() {
+        let (_, reasons) = scored(
+            &card(&json!({"type": "Insect"})),
+            &card(&json!({"type": "insect"})),
+        );
+        assert!(reasons.iter().any(|(_, x)| x == "Also an insect"));
+    }
+}
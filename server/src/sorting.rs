@@ -0,0 +1,205 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use hemoglobin::cards::properties::Read;
+use hemoglobin::cards::Card;
+use hemoglobin::numbers::ImpreciseOrd;
+use hemoglobin::search::query_parser::{get_property_from_name, Properties};
+use hemoglobin::search::{Ordering, Query, Sort};
+use serde::Serialize;
+
+use crate::ranking::Relevance;
+
+/// A sort that was applied to search results, as reported back to the client.
+#[derive(Serialize)]
+pub struct AppliedSort {
+    property: String,
+    order: String,
+}
+
+fn parse_order(order: &str) -> Result<Ordering, String> {
+    match order {
+        "asc" | "ascending" => Ok(Ordering::Ascending),
+        "desc" | "descending" => Ok(Ordering::Descending),
+        _ => Err(format!("Not a valid order: {order}")),
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|x| !x.is_empty())
+}
+
+/// Parses the `sort` and `order` search parameters. Each key in `sort` takes the order at the same position in `order`. Keys without one take the last order given, or ascending if there is none.
+/// A `sort` without any keys is an error rather than no sort at all, since it would still override the query's own sort.
+pub fn parse_sort(sort: &str, order: Option<&str>) -> Result<Vec<Sort>, String> {
+    let orders = split_list(order.unwrap_or_default())
+        .map(parse_order)
+        .collect::<Result<Vec<_>, _>>()?;
+    if split_list(sort).next().is_none() {
+        return Err("No sort keys were given".to_string());
+    }
+
+    split_list(sort)
+        .enumerate()
+        .map(|(idx, key)| {
+            let order = orders
+                .get(idx)
+                .or_else(|| orders.last())
+                .copied()
+                .unwrap_or(Ordering::Ascending);
+            match get_property_from_name(key) {
+                Ok(Properties::NumProperty(property)) => Ok(Sort::Numeric(property, order)),
+                Ok(Properties::StringProperty(property)) => Ok(Sort::Alphabet(property, order)),
+                _ => Err(format!("Can't sort by {key}")),
+            }
+        })
+        .collect()
+}
+
+/// Flips the direction of a query's own sort, for when only `order` is given. Queries that aren't sorted by a property have no direction to flip, so that's an error instead of being ignored.
+pub fn with_order(sort: Sort, order: Ordering) -> Result<Sort, String> {
+    match sort {
+        Sort::Alphabet(property, _) => Ok(Sort::Alphabet(property, order)),
+        Sort::Numeric(property, _) => Ok(Sort::Numeric(property, order)),
+        Sort::None | Sort::Fuzzy => {
+            Err("order needs a sort key when the query isn't sorted by a property".to_string())
+        }
+    }
+}
+
+/// Parses a single order, for when `order` is given without `sort`.
+pub fn parse_single_order(order: &str) -> Result<Ordering, String> {
+    let mut orders = split_list(order);
+    match (orders.next(), orders.next()) {
+        (Some(order), None) => parse_order(order),
+        _ => Err("Multiple orders need a sort key each".to_string()),
+    }
+}
+
+fn compare(a: &Card, b: &Card, sort: Sort, relevance: &HashMap<&str, Relevance>) -> cmp::Ordering {
+    let (ordering, order) = match sort {
+        Sort::Numeric(property, order) => (
+            a.get_num_property(&property)
+                .imprecise_cmp(&b.get_num_property(&property)),
+            order,
+        ),
+        Sort::Alphabet(property, order) => (
+            a.get_text_property(&property)
+                .cmp(&b.get_text_property(&property)),
+            order,
+        ),
+        Sort::Fuzzy if relevance.is_empty() => (a.name.cmp(&b.name), Ordering::Ascending),
+        Sort::Fuzzy => (
+            relevance[a.id.as_str()]
+                .score
+                .total_cmp(&relevance[b.id.as_str()].score),
+            Ordering::Descending,
+        ),
+        Sort::None => return cmp::Ordering::Equal,
+    };
+    match order {
+        Ordering::Ascending => ordering,
+        Ordering::Descending => ordering.reverse(),
+    }
+}
+
+/// Sorts cards by every key in turn, using the next key to break ties. Cards that tie on every key are sorted by ID, so results are always in the same order.
+/// Fuzzy sorts rank cards by their `relevance`, or sort them by name if there's none, since that means the query has no words to rank by.
+pub fn sort_cards(cards: &mut [&Card], sorts: &[Sort], relevance: &HashMap<&str, Relevance>) {
+    cards.sort_by(|a, b| {
+        sorts
+            .iter()
+            .map(|sort| compare(a, b, *sort, relevance))
+            .find(|x| x.is_ne())
+            .unwrap_or_else(|| a.id.cmp(&b.id))
+    });
+}
+
+/// Describes the sorts that were applied to a search, in priority order.
+pub fn applied(query: &Query, sorts: &[Sort]) -> Vec<AppliedSort> {
+    sorts
+        .iter()
+        .filter_map(|sort| {
+            let (property, order) = match sort {
+                Sort::None => return None,
+                Sort::Fuzzy if query.name.is_empty() => ("Name".to_string(), Ordering::Ascending),
+                Sort::Fuzzy => ("Relevance".to_string(), Ordering::Descending),
+                Sort::Alphabet(property, order) => (property.to_string(), *order),
+                Sort::Numeric(property, order) => (property.to_string(), *order),
+            };
+            Some(AppliedSort {
+                property,
+                order: order.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hemoglobin::search::query_parser::query_parser;
+    use hemoglobin::search::{Ordering, Sort};
+
+    use super::{parse_single_order, parse_sort, with_order};
+
+    /// Hemoglobin's sorts can't be compared, so they're compared by how they print.
+    fn sorts(sort: &str, order: Option<&str>) -> Result<Vec<String>, String> {
+        parse_sort(sort, order).map(|x| x.iter().map(|x: &Sort| format!("{x:?}")).collect())
+    }
+
+    #[test]
+    fn orders_line_up_with_keys() {
+        assert_eq!(
+            sorts("cost, name", Some("desc,asc")).unwrap(),
+            ["Numeric(Cost, Descending)", "Alphabet(Name, Ascending)"]
+        );
+        // Keys without an order of their own take the last one given.
+        assert_eq!(
+            sorts("h,p,,n", Some("descending")).unwrap(),
+            [
+                "Numeric(Health, Descending)",
+                "Numeric(Power, Descending)",
+                "Alphabet(Name, Descending)"
+            ]
+        );
+        assert_eq!(sorts("t", None).unwrap(), ["Alphabet(Type, Ascending)"]);
+    }
+
+    #[test]
+    fn invalid_sorts() {
+        assert_eq!(sorts("kin", None), Err("Can't sort by kin".to_string()));
+        assert_eq!(sorts("smell", None), Err("Can't sort by smell".to_string()));
+        assert_eq!(
+            sorts("cost", Some("up")),
+            Err("Not a valid order: up".to_string())
+        );
+        // An empty `sort` would otherwise drop the query's own sort.
+        assert_eq!(sorts("", None), Err("No sort keys were given".to_string()));
+        assert_eq!(
+            sorts(" , ", Some("desc")),
+            Err("No sort keys were given".to_string())
+        );
+    }
+
+    #[test]
+    fn single_orders() {
+        assert!(parse_single_order(" desc ").is_ok());
+        assert!(parse_single_order("sideways").is_err());
+        assert_eq!(
+            parse_single_order("asc,desc").map(|_| ()),
+            Err("Multiple orders need a sort key each".to_string())
+        );
+    }
+
+    #[test]
+    fn orders_without_keys() {
+        let sort = |query: &str| query_parser(query).unwrap().sort;
+        assert_eq!(
+            with_order(sort("so:cost"), Ordering::Descending).map(|x| format!("{x:?}")),
+            Ok("Numeric(Cost, Descending)".to_string())
+        );
+        // Fuzzy sorts and unsorted queries have no direction for `order` to change.
+        assert!(with_order(sort("mantis"), Ordering::Descending).is_err());
+        assert!(with_order(Sort::None, Ordering::Ascending).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{BTreeSet, HashMap};
+
+use actix_web::{web, HttpResponse, Responder};
+use hemoglobin::cards::properties::{Array, Text};
+use hemoglobin::cards::Card;
+use hemoglobin::search::query_parser::{get_property_from_name, Properties};
+use serde::{Deserialize, Serialize};
+
+use crate::{normalize, AppState};
+
+const MAX_SUGGESTIONS: usize = 10;
+
+/// Every parameter name `query_parser` accepts, including aliases.
+const PARAMETERS: [&str; 46] = [
+    "id",
+    "name",
+    "n",
+    "flavortext",
+    "flavor",
+    "ft",
+    "description",
+    "desc",
+    "de",
+    "type",
+    "t",
+    "cost",
+    "c",
+    "health",
+    "h",
+    "hp",
+    "power",
+    "strength",
+    "damage",
+    "p",
+    "dmg",
+    "str",
+    "defense",
+    "defence",
+    "def",
+    "d",
+    "kin",
+    "k",
+    "function",
+    "fun",
+    "fn",
+    "f",
+    "keyword",
+    "kw",
+    "sort",
+    "so",
+    "sortd",
+    "sod",
+    "devours",
+    "dev",
+    "devs",
+    "devouredby",
+    "devby",
+    "deby",
+    "dby",
+    "db",
+];
+
+/// A value that can be suggested, along with its folded form, which is what it's matched against.
+pub struct Entry {
+    pub value: String,
+    pub folded: String,
+}
+
+impl Entry {
+    fn new(value: &str) -> Self {
+        Self {
+            value: value.to_string(),
+            folded: normalize::fold(value),
+        }
+    }
+}
+
+/// Values that can be suggested, built from the card map every time it's loaded. Values are folded once here, so completing doesn't fold every value on every keystroke.
+pub struct Vocabulary {
+    names: Vec<Entry>,
+    kins: Vec<Entry>,
+    keywords: Vec<Entry>,
+    types: Vec<Entry>,
+    artists: Vec<Entry>,
+    functions: Vec<Entry>,
+    parameters: Vec<Entry>,
+    /// Every word in a card name, lowercased, which misspelled words in queries are corrected to.
+    name_words: Vec<Entry>,
+}
+
+impl Vocabulary {
+    pub fn new(cards: &HashMap<String, Card>) -> Self {
+        fn collect<'a>(values: impl Iterator<Item = &'a String>) -> Vec<Entry> {
+            values
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .map(|x| Entry::new(x))
+                .collect()
+        }
+
+        let name_words: BTreeSet<String> = cards
+            .values()
+            .flat_map(|x| x.name.split_whitespace())
+            .map(str::to_lowercase)
+            .collect();
+
+        Self {
+            names: collect(cards.values().map(|x| &x.name)),
+            kins: collect(cards.values().flat_map(|x| &x.kins)),
+            keywords: collect(
+                cards
+                    .values()
+                    .flat_map(|x| x.keywords.iter().map(|x| &x.name)),
+            ),
+            types: collect(cards.values().map(|x| &x.r#type)),
+            artists: collect(cards.values().flat_map(Card::get_artists)),
+            functions: collect(cards.values().flat_map(|x| &x.functions)),
+            parameters: PARAMETERS.iter().map(|x| Entry::new(x)).collect(),
+            name_words: collect(name_words.iter()),
+        }
+    }
+
+    pub fn name_words(&self) -> &[Entry] {
+        &self.name_words
+    }
+
+    pub fn values(&self, context: Context) -> &[Entry] {
+        match context {
+            Context::Name => &self.names,
+            Context::Kin => &self.kins,
+            Context::Keyword => &self.keywords,
+            Context::Type => &self.types,
+            Context::Artist => &self.artists,
+            Context::Function => &self.functions,
+            Context::Parameter => &self.parameters,
+        }
+    }
+}
+
+/// What kind of value is being completed.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Context {
+    Name,
+    Kin,
+    Keyword,
+    Type,
+    Artist,
+    Function,
+    Parameter,
+}
+
+#[derive(Deserialize)]
+pub struct SuggestParams {
+    #[serde(default)]
+    prefix: String,
+    /// If this is missing, it's guessed from the prefix. `k:ins` completes kins, while a prefix without a parameter completes card names and parameter names.
+    context: Option<Context>,
+}
+
+#[derive(Serialize)]
+struct Suggestions {
+    context: Option<Context>,
+    /// Text that can replace the prefix.
+    suggestions: Vec<String>,
+}
+
+/// The context a query parameter's value is completed in, if it has values worth completing.
+pub fn parameter_context(parameter: &str) -> Option<Context> {
+    match get_property_from_name(parameter).ok()? {
+        Properties::StringProperty(Text::Name) => Some(Context::Name),
+        Properties::StringProperty(Text::Type) => Some(Context::Type),
+        Properties::ArrayProperty(Array::Kins) => Some(Context::Kin),
+        Properties::ArrayProperty(Array::Functions) => Some(Context::Function),
+        Properties::Keywords => Some(Context::Keyword),
+        _ => None,
+    }
+}
+
+/// Values that start with the prefix come first, then values with a later word that starts with it.
+fn complete<'a>(values: &'a [Entry], prefix: &str) -> Vec<&'a str> {
+    let prefix = normalize::fold(prefix);
+    let mut starts = vec![];
+    let mut words = vec![];
+    for entry in values {
+        if entry.folded.starts_with(&prefix) {
+            starts.push(entry.value.as_str());
+        } else if entry
+            .folded
+            .split_whitespace()
+            .skip(1)
+            .any(|word| word.starts_with(&prefix))
+        {
+            words.push(entry.value.as_str());
+        }
+        if starts.len() >= MAX_SUGGESTIONS {
+            break;
+        }
+    }
+    starts.extend(words);
+    starts.truncate(MAX_SUGGESTIONS);
+    starts
+}
+
+/// Completes `prefix`, guessing what it completes from the prefix itself if there's no `context`.
+fn suggestions(
+    vocabulary: &Vocabulary,
+    prefix: &str,
+    context: Option<Context>,
+) -> (Option<Context>, Vec<String>) {
+    // Negated terms are completed like any other, and keep their negation.
+    let term = prefix.trim_start_matches('-');
+    let negation = &prefix[..prefix.len() - term.len()];
+
+    match (context, term.split_once(':')) {
+        (Some(context), _) => (
+            Some(context),
+            complete(vocabulary.values(context), term)
+                .into_iter()
+                .map(ToString::to_string)
+                .collect(),
+        ),
+        (None, Some((parameter, value))) => parameter_context(parameter).map_or_else(
+            || (None, vec![]),
+            |context| {
+                let suggestions =
+                    complete(vocabulary.values(context), value.trim_start_matches('"'))
+                        .into_iter()
+                        .map(|x| {
+                            if x.contains(' ') {
+                                format!("{negation}{parameter}:\"{x}\"")
+                            } else {
+                                format!("{negation}{parameter}:{x}")
+                            }
+                        })
+                        .collect();
+                (Some(context), suggestions)
+            },
+        ),
+        (None, None) => (
+            None,
+            complete(vocabulary.values(Context::Parameter), term)
+                .into_iter()
+                .map(|x| format!("{negation}{x}:"))
+                .chain(
+                    complete(vocabulary.values(Context::Name), term)
+                        .into_iter()
+                        .map(|x| format!("{negation}{x}")),
+                )
+                .take(MAX_SUGGESTIONS)
+                .collect(),
+        ),
+    }
+}
+
+pub async fn suggest(
+    data: web::Data<AppState>,
+    params: web::Query<SuggestParams>,
+) -> impl Responder {
+    let vocabulary = data.vocabulary.read().await;
+    let (context, suggestions) = suggestions(&vocabulary, &params.prefix, params.context);
+
+    HttpResponse::Ok().json(Suggestions {
+        context,
+        suggestions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hemoglobin::cards::Card;
+    use serde_json::json;
+
+    use super::{parameter_context, suggestions, Context, Vocabulary, MAX_SUGGESTIONS};
+
+    fn vocabulary() -> Vocabulary {
+        let cards = [
+            ("mantis", "Vampire Mantis", "bug"),
+            ("moth", "Moth", "bug"),
+            ("mole", "Mólé Rat", "mammal"),
+            ("queen", "Queen of Moths", "bug"),
+        ];
+        let cards: HashMap<String, Card> = cards
+            .into_iter()
+            .map(|(id, name, kin)| {
+                let card = json!({
+                    "id": id, "name": name, "description": "", "cost": 1, "health": 1,
+                    "defense": 0, "power": 1, "type": "creature", "kins": [kin], "legality": {},
+                });
+                (
+                    id.to_string(),
+                    serde_json::from_str(&card.to_string()).unwrap(),
+                )
+            })
+            .collect();
+        Vocabulary::new(&cards)
+    }
+
+    #[test]
+    fn contexts() {
+        assert_eq!(parameter_context("n"), Some(Context::Name));
+        assert_eq!(parameter_context("k"), Some(Context::Kin));
+        assert_eq!(parameter_context("kw"), Some(Context::Keyword));
+        assert_eq!(parameter_context("t"), Some(Context::Type));
+        assert_eq!(parameter_context("fn"), Some(Context::Function));
+        // Numbers have nothing worth completing.
+        assert_eq!(parameter_context("c"), None);
+        assert_eq!(parameter_context("smell"), None);
+
+        let vocabulary = vocabulary();
+        assert_eq!(
+            suggestions(&vocabulary, "k:ma", None),
+            (Some(Context::Kin), vec!["k:mammal".to_string()])
+        );
+        // Values with spaces are quoted, and negations are kept.
+        assert_eq!(
+            suggestions(&vocabulary, "-n:\"vamp", None),
+            (
+                Some(Context::Name),
+                vec!["-n:\"Vampire Mantis\"".to_string()]
+            )
+        );
+        assert_eq!(suggestions(&vocabulary, "c:1", None), (None, vec![]));
+        // A given context completes the whole prefix.
+        assert_eq!(
+            suggestions(&vocabulary, "bu", Some(Context::Kin)),
+            (Some(Context::Kin), vec!["bug".to_string()])
+        );
+    }
+
+    #[test]
+    fn ranking() {
+        let vocabulary = vocabulary();
+        // Without a parameter, parameter names come before card names.
+        assert_eq!(
+            suggestions(&vocabulary, "k", None).1,
+            ["kin:", "k:", "keyword:", "kw:"]
+        );
+        // Names that start with the prefix come before names with a later word that does, and accents and case don't matter.
+        assert_eq!(
+            suggestions(&vocabulary, "MO", Some(Context::Name)).1,
+            ["Moth", "Mólé Rat", "Queen of Moths"]
+        );
+        assert_eq!(
+            suggestions(&vocabulary, "mole", Some(Context::Name)).1,
+            ["Mólé Rat"]
+        );
+        assert!(suggestions(&vocabulary, "", None).1.len() <= MAX_SUGGESTIONS);
+    }
+}
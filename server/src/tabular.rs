@@ -0,0 +1,261 @@
+use std::convert::Infallible;
+
+use actix_web::web::{self, Bytes};
+use actix_web::HttpResponse;
+use futures_util::{stream, StreamExt};
+use hemoglobin::cards::Card;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::AppState;
+
+/// How many cards are written per chunk. The card lock is only held while a chunk is written, so reloads aren't blocked by slow downloads.
+const CHUNK_SIZE: usize = 256;
+
+/// Formats search results can be exported as, instead of the usual JSON.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TableFormat {
+    Csv,
+    Tsv,
+    Ndjson,
+}
+
+impl TableFormat {
+    const fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Tsv => "text/tab-separated-values; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Column {
+    Name,
+    Type,
+    Cost,
+    Health,
+    Defense,
+    Power,
+    Kins,
+    Keywords,
+    Set,
+}
+
+impl Column {
+    const ALL: [Self; 9] = [
+        Self::Name,
+        Self::Type,
+        Self::Cost,
+        Self::Health,
+        Self::Defense,
+        Self::Power,
+        Self::Kins,
+        Self::Keywords,
+        Self::Set,
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.name() == name)
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Type => "type",
+            Self::Cost => "cost",
+            Self::Health => "health",
+            Self::Defense => "defense",
+            Self::Power => "power",
+            Self::Kins => "kins",
+            Self::Keywords => "keywords",
+            Self::Set => "set",
+        }
+    }
+
+    /// The column as a single cell. Lists are separated by semicolons.
+    fn text(self, card: &Card) -> String {
+        match self {
+            Self::Name => card.name.clone(),
+            Self::Type => card.r#type.clone(),
+            Self::Cost => card.cost.to_string(),
+            Self::Health => card.health.to_string(),
+            Self::Defense => card.defense.to_string(),
+            Self::Power => card.power.to_string(),
+            Self::Kins => card.kins.join("; "),
+            Self::Keywords => card
+                .keywords
+                .iter()
+                .map(|x| x.name.as_str())
+                .collect::<Vec<_>>()
+                .join("; "),
+            Self::Set => card.set.clone(),
+        }
+    }
+
+    fn json(self, card: &Card) -> Value {
+        match self {
+            Self::Cost => serde_json::to_value(&card.cost),
+            Self::Health => serde_json::to_value(&card.health),
+            Self::Defense => serde_json::to_value(&card.defense),
+            Self::Power => serde_json::to_value(&card.power),
+            Self::Kins => serde_json::to_value(&card.kins),
+            Self::Keywords => serde_json::to_value(&card.keywords),
+            Self::Name | Self::Type | Self::Set => Ok(Value::String(self.text(card))),
+        }
+        .unwrap_or(Value::Null)
+    }
+}
+
+/// Parses a comma separated list of columns. Every column is included if there is no list, but a list without any columns is an error, since it would only give empty rows.
+pub fn parse_columns(columns: Option<&str>) -> Result<Vec<Column>, String> {
+    let Some(columns) = columns else {
+        return Ok(Column::ALL.to_vec());
+    };
+    let columns = columns
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|name| Column::from_name(name).ok_or_else(|| format!("Unknown column: {name}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    if columns.is_empty() {
+        return Err("No columns were given".to_string());
+    }
+    Ok(columns)
+}
+
+fn write_rows(format: TableFormat, rows: impl Iterator<Item = Vec<String>>) -> Bytes {
+    let delimiter = match format {
+        TableFormat::Tsv => b'\t',
+        TableFormat::Csv | TableFormat::Ndjson => b',',
+    };
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+    for row in rows {
+        // Writing to a Vec can't fail.
+        let _ = writer.write_record(row);
+    }
+    writer
+        .into_inner()
+        .map_or_else(|_| Bytes::new(), Bytes::from)
+}
+
+fn write_chunk(format: TableFormat, columns: &[Column], cards: &[&Card]) -> Bytes {
+    match format {
+        TableFormat::Csv | TableFormat::Tsv => write_rows(
+            format,
+            cards
+                .iter()
+                .map(|card| columns.iter().map(|x| x.text(card)).collect()),
+        ),
+        TableFormat::Ndjson => {
+            let mut out = String::new();
+            for card in cards {
+                // Written by hand rather than through a `Map`, so keys keep the order columns were asked for.
+                let fields: Vec<String> = columns
+                    .iter()
+                    .map(|x| format!("\"{}\":{}", x.name(), x.json(card)))
+                    .collect();
+                out.push('{');
+                out.push_str(&fields.join(","));
+                out.push_str("}\n");
+            }
+            Bytes::from(out)
+        }
+    }
+}
+
+/// Streams search results as a table, a chunk of cards at a time.
+pub fn respond(
+    data: web::Data<AppState>,
+    format: TableFormat,
+    columns: Vec<Column>,
+    ids: &[String],
+) -> HttpResponse {
+    let header = match format {
+        TableFormat::Csv | TableFormat::Tsv => Some(write_rows(
+            format,
+            std::iter::once(columns.iter().map(|x| x.name().to_string()).collect()),
+        )),
+        TableFormat::Ndjson => None,
+    };
+
+    let chunks: Vec<Vec<String>> = ids.chunks(CHUNK_SIZE).map(<[String]>::to_vec).collect();
+    let rows = stream::iter(chunks).then(move |chunk| {
+        let data = data.clone();
+        let columns = columns.clone();
+        async move {
+            let cards = data.cards.read().await;
+            // Cards removed by a reload since the search ran are skipped.
+            let chunk: Vec<&Card> = chunk.iter().filter_map(|id| cards.get(id)).collect();
+            Ok::<_, Infallible>(write_chunk(format, &columns, &chunk))
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .streaming(stream::iter(header.map(Ok)).chain(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use hemoglobin::cards::Card;
+    use serde_json::json;
+
+    use super::{parse_columns, write_chunk, Column, TableFormat};
+
+    fn names(columns: Result<Vec<Column>, String>) -> Result<Vec<&'static str>, String> {
+        columns.map(|x| x.into_iter().map(Column::name).collect())
+    }
+
+    fn card(name: &str, kins: &[&str]) -> Card {
+        let card = json!({
+            "id": name, "name": name, "description": "", "cost": 2, "health": 1, "defense": 0,
+            "power": 3, "type": "creature", "kins": kins, "legality": {},
+        });
+        serde_json::from_str(&card.to_string()).unwrap()
+    }
+
+    #[test]
+    fn columns() {
+        assert_eq!(names(parse_columns(None)).unwrap().len(), Column::ALL.len());
+        assert_eq!(
+            names(parse_columns(Some(" cost,name ,,"))),
+            Ok(vec!["cost", "name"])
+        );
+        assert_eq!(
+            names(parse_columns(Some("name,smell"))),
+            Err("Unknown column: smell".to_string())
+        );
+        assert!(parse_columns(Some("")).is_err());
+        assert!(parse_columns(Some(" , ")).is_err());
+    }
+
+    #[test]
+    fn chunks() {
+        let (mantis, ant) = (
+            card("Mantis, Vampire", &["bug"]),
+            card("Ant", &["bug", "ant"]),
+        );
+        let cards = [&mantis, &ant];
+        let columns = [Column::Name, Column::Power, Column::Kins];
+
+        assert_eq!(
+            write_chunk(TableFormat::Csv, &columns, &cards),
+            "\"Mantis, Vampire\",3,bug\nAnt,3,bug; ant\n"
+        );
+        assert_eq!(
+            write_chunk(TableFormat::Tsv, &columns, &cards),
+            "Mantis, Vampire\t3\tbug\nAnt\t3\tbug; ant\n"
+        );
+        // Lines keep the order the columns were asked for, and lists stay lists.
+        assert_eq!(
+            write_chunk(TableFormat::Ndjson, &[Column::Kins, Column::Cost], &cards),
+            "{\"kins\":[\"bug\"],\"cost\":2}\n{\"kins\":[\"bug\",\"ant\"],\"cost\":2}\n"
+        );
+        assert!(write_chunk(TableFormat::Csv, &columns, &[]).is_empty());
+    }
+}